@@ -0,0 +1,367 @@
+use syn::meta::ParseNestedMeta;
+use syn::punctuated::Punctuated;
+use quote::format_ident;
+use syn::{Attribute, Expr, Ident, LitByteStr, LitInt, LitStr, Member, Path, Token, Type, WherePredicate};
+
+/// `#[bytepack(...)]` options on a struct or enum
+#[derive(Default)]
+pub struct ContainerAttrs {
+    /// Path to the bytepack crate, `#[bytepack(crate = "my_reexport::bytepack")]`
+    pub krate: Option<Path>,
+    /// Wire type of an enum tag, `#[bytepack(tag_type = "LEu16")]`
+    pub tag_type: Option<Type>,
+    /// Where predicates replacing the inferred bounds of each derive,
+    /// `#[bytepack(bound = "T: BytePack")]` or `#[bytepack(bound(pack = "..", unpack = ".."))]`
+    pub bounds: Bounds,
+    /// Byte order of every plain integer and float field, `#[bytepack(endian = "little")]`
+    pub endian: Option<Ident>,
+    /// Trailing padding up to a multiple of this many bytes, `#[bytepack(align = 4)]`
+    pub align: Option<usize>,
+    /// Trailing padding up to this many bytes in total, `#[bytepack(pad_to = 64)]`.
+    /// Longer content fails to pack and unpack, `byte_size` still counts all of it.
+    pub pad_to: Option<usize>,
+    /// Which end of a bitfield group its first field occupies, `#[bytepack(bit_order = "lsb")]`
+    pub bit_order: BitOrder,
+    /// Constant bytes in front of the fields, `#[bytepack(magic = b"\x89PNG\r\n\x1a\n")]`
+    pub magic: Option<LitByteStr>,
+}
+
+/// Placement of consecutive `bits` fields within their shared integer
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum BitOrder {
+    /// The first field takes the most significant bits, as in network protocol diagrams
+    #[default]
+    Msb,
+    /// The first field takes the least significant bits, as C compilers do on little-endian targets
+    Lsb,
+}
+
+#[derive(Default)]
+pub struct Bounds {
+    pub const_size: Option<Vec<WherePredicate>>,
+    pub size: Option<Vec<WherePredicate>>,
+    pub pack: Option<Vec<WherePredicate>>,
+    pub unpack: Option<Vec<WherePredicate>>,
+}
+
+/// `#[bytepack(...)]` options on an enum variant
+#[derive(Default)]
+pub struct VariantAttrs {
+    /// Tag value of the variant, `#[bytepack(tag = 0x10)]`
+    pub tag: Option<Expr>,
+}
+
+/// `#[bytepack(...)]` options on a field
+#[derive(Default)]
+pub struct FieldAttrs {
+    /// Earlier field holding the tag of this enum field, `#[bytepack(tag_from = "kind")]`
+    pub tag_from: Option<Member>,
+    /// Pack the tag field from the variant instead of checking it, `#[bytepack(fill_tag)]`
+    pub fill_tag: bool,
+    /// Byte order of this field, `#[bytepack(endian = "little")]`
+    pub endian: Option<Ident>,
+    /// Pick the byte order of all later fields from this field, `#[bytepack(byte_order_mark)]`
+    pub byte_order_mark: bool,
+    /// Condition on earlier fields deciding whether this `Option` field is on the wire,
+    /// `#[bytepack(if = "flags & 0x1 != 0")]`
+    pub present_if: Option<Expr>,
+    /// Encoding of a sequence field, `#[bytepack(sized)]`, `#[bytepack(splat)]` or `#[bytepack(drain)]`
+    pub sequence: Option<Sequence>,
+    /// Length prefix type of a `sized` field in place of `SizeType`, `#[bytepack(sized = "u16")]`
+    pub len_type: Option<Type>,
+    /// Length of a sequence field taken from earlier fields,
+    /// `#[bytepack(count = "count")]` or `#[bytepack(bytes = "len - 4")]`
+    pub len_from: Option<LenFrom>,
+    /// Pack the length field from the sequence instead of checking it, `#[bytepack(fill_len)]`
+    pub fill_len: bool,
+    /// Leading padding so the field starts at a multiple of this many bytes, `#[bytepack(align = 4)]`
+    pub align: Option<usize>,
+    /// Trailing padding up to this many bytes for the field, `#[bytepack(pad_to = 16)]`.
+    /// Longer content fails to pack and unpack, `byte_size` still counts all of it.
+    pub pad_to: Option<usize>,
+    /// Width of the field in a group of consecutive bitfields, `#[bytepack(bits = 4)]`
+    pub bits: Option<u32>,
+    /// Check on this and earlier fields, `#[bytepack(assert = "version == 4")]`
+    pub assert: Option<Assertion>,
+}
+
+/// A condition a field must meet, kept with its source text for the error
+pub struct Assertion {
+    pub expr: Expr,
+    pub text: String,
+}
+
+/// Where the length of a sequence field without a length prefix comes from
+pub enum LenFrom {
+    /// Number of elements
+    Count(Expr),
+    /// Number of bytes the elements occupy
+    Bytes(Expr),
+}
+
+impl LenFrom {
+    pub fn expr(&self) -> &Expr {
+        match self {
+            Self::Count(expr) | Self::Bytes(expr) => expr,
+        }
+    }
+}
+
+/// How a sequence field is laid out on the wire
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Sequence {
+    /// Behind a length prefix, the default of the sequence types
+    Sized,
+    /// Back to back without a length prefix, so it can be packed but not unpacked
+    Splat,
+    /// Back to back without a length prefix, unpacked until the end of the input
+    Drain,
+}
+
+impl ContainerAttrs {
+    pub fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut container = Self::default();
+        for_each_bytepack_meta(attrs, |meta| {
+            if meta.path.is_ident("crate") {
+                container.krate = Some(parse_str(&meta)?);
+            } else if meta.path.is_ident("tag_type") {
+                container.tag_type = Some(parse_str(&meta)?);
+            } else if meta.path.is_ident("bound") {
+                container.bounds.parse(&meta)?;
+            } else if meta.path.is_ident("endian") {
+                container.endian = Some(parse_endian(&meta)?);
+            } else if meta.path.is_ident("align") {
+                container.align = Some(parse_align(&meta)?);
+            } else if meta.path.is_ident("pad_to") {
+                container.pad_to = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+            } else if meta.path.is_ident("bit_order") {
+                container.bit_order = parse_bit_order(&meta)?;
+            } else if meta.path.is_ident("magic") {
+                container.magic = Some(meta.value()?.parse()?);
+            } else {
+                return Err(meta.error("unknown bytepack container attribute"));
+            }
+            Ok(())
+        })?;
+        Ok(container)
+    }
+
+    /// Path generated code uses to name bytepack items, `::bytepack` unless overridden
+    pub fn crate_path(&self) -> Path {
+        self.krate
+            .clone()
+            .unwrap_or_else(|| syn::parse_quote!(::bytepack))
+    }
+}
+
+impl Bounds {
+    fn parse(&mut self, meta: &ParseNestedMeta) -> syn::Result<()> {
+        if meta.input.peek(Token![=]) {
+            let predicates = parse_predicates(meta)?;
+            self.const_size = Some(predicates.clone());
+            self.size = Some(predicates.clone());
+            self.pack = Some(predicates.clone());
+            self.unpack = Some(predicates);
+            return Ok(());
+        }
+        meta.parse_nested_meta(|meta| {
+            let predicates = Some(parse_predicates(&meta)?);
+            if meta.path.is_ident("const_size") {
+                self.const_size = predicates;
+            } else if meta.path.is_ident("size") {
+                self.size = predicates;
+            } else if meta.path.is_ident("pack") {
+                self.pack = predicates;
+            } else if meta.path.is_ident("unpack") {
+                self.unpack = predicates;
+            } else {
+                return Err(meta.error("expected one of `const_size`, `size`, `pack`, `unpack`"));
+            }
+            Ok(())
+        })
+    }
+}
+
+impl VariantAttrs {
+    pub fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut variant = Self::default();
+        for_each_bytepack_meta(attrs, |meta| {
+            if meta.path.is_ident("tag") {
+                variant.tag = Some(meta.value()?.parse()?);
+            } else {
+                return Err(meta.error("unknown bytepack variant attribute"));
+            }
+            Ok(())
+        })?;
+        Ok(variant)
+    }
+}
+
+impl FieldAttrs {
+    pub fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut field = Self::default();
+        for_each_bytepack_meta(attrs, |meta| {
+            if meta.path.is_ident("tag_from") {
+                field.tag_from = Some(parse_str(&meta)?);
+            } else if meta.path.is_ident("fill_tag") {
+                field.fill_tag = true;
+            } else if meta.path.is_ident("endian") {
+                field.endian = Some(parse_endian(&meta)?);
+            } else if meta.path.is_ident("byte_order_mark") {
+                field.byte_order_mark = true;
+            } else if meta.path.is_ident("if") {
+                field.present_if = Some(parse_str(&meta)?);
+            } else if let Some(sequence) = Sequence::from_path(&meta.path) {
+                if field.sequence.is_some() {
+                    return Err(meta.error("only one of `sized`, `splat`, `drain` is allowed"));
+                }
+                field.sequence = Some(sequence);
+                if sequence == Sequence::Sized && meta.input.peek(Token![=]) {
+                    field.len_type = Some(parse_str(&meta)?);
+                }
+            } else if meta.path.is_ident("count") || meta.path.is_ident("bytes") {
+                if field.len_from.is_some() {
+                    return Err(meta.error("only one of `count`, `bytes` is allowed"));
+                }
+                let expr = parse_str(&meta)?;
+                field.len_from = Some(match meta.path.is_ident("count") {
+                    true => LenFrom::Count(expr),
+                    false => LenFrom::Bytes(expr),
+                });
+            } else if meta.path.is_ident("fill_len") {
+                field.fill_len = true;
+            } else if meta.path.is_ident("align") {
+                field.align = Some(parse_align(&meta)?);
+            } else if meta.path.is_ident("pad_to") {
+                field.pad_to = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+            } else if meta.path.is_ident("bits") {
+                field.bits = Some(parse_bits(&meta)?);
+            } else if meta.path.is_ident("assert") {
+                let lit: LitStr = meta.value()?.parse()?;
+                field.assert = Some(Assertion {
+                    expr: lit.parse()?,
+                    text: lit.value(),
+                });
+            } else {
+                return Err(meta.error("unknown bytepack field attribute"));
+            }
+            Ok(())
+        })?;
+        Ok(field)
+    }
+
+    /// Whether any attribute other than `bits`, `endian` and `assert` is set, none of which a bitfield takes
+    pub fn has_non_bits_attrs(&self) -> bool {
+        self.tag_from.is_some()
+            || self.fill_tag
+            || self.byte_order_mark
+            || self.present_if.is_some()
+            || self.sequence.is_some()
+            || self.len_from.is_some()
+            || self.fill_len
+            || self.align.is_some()
+            || self.pad_to.is_some()
+    }
+
+    /// Whether this sequence field is laid out without a length prefix of its own
+    pub fn is_unprefixed_sequence(&self) -> bool {
+        self.len_from.is_some() || matches!(self.sequence, Some(Sequence::Splat | Sequence::Drain))
+    }
+}
+
+impl Sequence {
+    fn from_path(path: &Path) -> Option<Self> {
+        if path.is_ident("sized") {
+            Some(Self::Sized)
+        } else if path.is_ident("splat") {
+            Some(Self::Splat)
+        } else if path.is_ident("drain") {
+            Some(Self::Drain)
+        } else {
+            None
+        }
+    }
+}
+
+/// Integer type named by `#[repr(..)]`, if any
+pub fn repr_int_type(attrs: &[Attribute]) -> syn::Result<Option<Type>> {
+    let mut repr = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("repr")) {
+        attr.parse_nested_meta(|meta| {
+            const INT_TYPES: [&str; 10] = [
+                "u8", "u16", "u32", "u64", "u128", "i8", "i16", "i32", "i64", "i128",
+            ];
+            if INT_TYPES.iter().any(|int_type| meta.path.is_ident(int_type)) {
+                let path = &meta.path;
+                repr = Some(syn::parse_quote!(#path));
+            } else if meta.input.peek(syn::token::Paren) {
+                // skip arguments of other reprs such as `align(4)`
+                let _args;
+                syn::parenthesized!(_args in meta.input);
+            }
+            Ok(())
+        })?;
+    }
+    Ok(repr)
+}
+
+fn for_each_bytepack_meta(
+    attrs: &[Attribute],
+    mut logic: impl FnMut(ParseNestedMeta) -> syn::Result<()>,
+) -> syn::Result<()> {
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("bytepack")) {
+        attr.parse_nested_meta(&mut logic)?;
+    }
+    Ok(())
+}
+
+/// Parse `endian = "big"` or `endian = "little"` into the matching `Endian` variant name
+fn parse_endian(meta: &ParseNestedMeta) -> syn::Result<Ident> {
+    let lit: LitStr = meta.value()?.parse()?;
+    match lit.value().as_str() {
+        "big" => Ok(format_ident!("Big", span = lit.span())),
+        "little" => Ok(format_ident!("Little", span = lit.span())),
+        _ => Err(syn::Error::new_spanned(lit, "expected \"big\" or \"little\"")),
+    }
+}
+
+/// Parse `align = N` where `N` is a non-zero byte count
+fn parse_align(meta: &ParseNestedMeta) -> syn::Result<usize> {
+    let lit: LitInt = meta.value()?.parse()?;
+    match lit.base10_parse()? {
+        0 => Err(syn::Error::new_spanned(lit, "alignment must be at least 1")),
+        align => Ok(align),
+    }
+}
+
+/// Parse `bits = N` where `N` is a width of 1 to 128 bits
+fn parse_bits(meta: &ParseNestedMeta) -> syn::Result<u32> {
+    let lit: LitInt = meta.value()?.parse()?;
+    match lit.base10_parse()? {
+        bits @ 1..=128 => Ok(bits),
+        _ => Err(syn::Error::new_spanned(lit, "a bitfield is 1 to 128 bits wide")),
+    }
+}
+
+/// Parse `bit_order = "msb"` or `bit_order = "lsb"`
+fn parse_bit_order(meta: &ParseNestedMeta) -> syn::Result<BitOrder> {
+    let lit: LitStr = meta.value()?.parse()?;
+    match lit.value().as_str() {
+        "msb" => Ok(BitOrder::Msb),
+        "lsb" => Ok(BitOrder::Lsb),
+        _ => Err(syn::Error::new_spanned(lit, "expected \"msb\" or \"lsb\"")),
+    }
+}
+
+/// Parse `key = "T: A, U: B"` into where predicates
+fn parse_predicates(meta: &ParseNestedMeta) -> syn::Result<Vec<WherePredicate>> {
+    let lit: LitStr = meta.value()?.parse()?;
+    let predicates = lit.parse_with(Punctuated::<WherePredicate, Token![,]>::parse_terminated)?;
+    Ok(predicates.into_iter().collect())
+}
+
+/// Parse `key = "..."` where the string holds Rust syntax
+fn parse_str<T: syn::parse::Parse>(meta: &ParseNestedMeta) -> syn::Result<T> {
+    let lit: LitStr = meta.value()?.parse()?;
+    lit.parse()
+}
@@ -0,0 +1,72 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::DeriveInput;
+
+use crate::attr::ContainerAttrs;
+use crate::fields::enum_tags;
+use crate::union_error;
+
+pub fn impl_bitfield(ast: &DeriveInput) -> TokenStream {
+    match bitfield(ast) {
+        Ok(gen) => gen.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// `BitField` for an enum whose variants have no fields, so the tag is the whole value
+fn bitfield(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &ast.ident;
+    let container = ContainerAttrs::parse(&ast.attrs)?;
+    let krate = &container.crate_path();
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    let data = match &ast.data {
+        syn::Data::Enum(data) => data,
+        syn::Data::Struct(_) => {
+            return Err(syn::Error::new_spanned(
+                &ast.ident,
+                "BitField can only be derived for enums without fields",
+            ));
+        },
+        syn::Data::Union(_) => return Err(union_error(ast)),
+    };
+    if let Some(variant) = data.variants.iter().find(|v| !v.fields.is_empty()) {
+        return Err(syn::Error::new_spanned(
+            variant,
+            "BitField can only be derived for enums without fields",
+        ));
+    }
+    let tags = enum_tags(ast, data)?;
+    let tag_type = &tags.tag_type;
+    let mut variants_to_bits = quote!();
+    let mut variants_from_bits = quote!();
+    for (variant, tag) in data.variants.iter().zip(&tags.values) {
+        let variant_name = &variant.ident;
+        variants_to_bits.extend(quote! {
+            Self::#variant_name => (#tag) as i128 as u128,
+        });
+        variants_from_bits.extend(quote! {
+            if __value == (#tag) as i128 {
+                return ::core::option::Option::Some(Self::#variant_name);
+            }
+        });
+    }
+    Ok(quote! {
+        #[allow(clippy::unnecessary_cast)]
+        impl #impl_generics #krate::base::BitField for #name #ty_generics #where_clause {
+            fn to_bits(&self) -> u128 {
+                match self {
+                    #variants_to_bits
+                }
+            }
+
+            fn from_bits(bits: u128) -> ::core::option::Option<Self> {
+                let __tag: #tag_type = #krate::base::Discriminant::try_from_discriminant(
+                    i128::try_from(bits).ok()?,
+                )?;
+                let __value = #krate::base::Discriminant::to_discriminant(&__tag);
+                #variants_from_bits
+                ::core::option::Option::None
+            }
+        }
+    })
+}
@@ -0,0 +1,489 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote, ToTokens};
+use syn::{
+    BinOp, DataEnum, DeriveInput, Expr, ExprLit, Fields, GenericArgument, Ident, Lit, Member,
+    PathArguments, Type,
+};
+
+use crate::attr::{repr_int_type, BitOrder, ContainerAttrs, FieldAttrs, LenFrom, Sequence, VariantAttrs};
+
+pub struct FieldInfo<'a> {
+    pub field: &'a syn::Field,
+    pub attrs: FieldAttrs,
+    /// Accessor on `self`, the name or the index of the field
+    pub member: Member,
+    /// Local variable the field is bound to in patterns and unpack code
+    pub binding: Ident,
+    /// `Endian` variant the field is packed with, from the field or the container attribute
+    pub endian: Option<Ident>,
+    /// Place of a `bits` field within its bitfield group
+    pub bit_slot: Option<BitSlot>,
+}
+
+/// Where a `bits` field sits in the integer it shares with its neighbours
+pub struct BitSlot {
+    /// Width of the field in bits
+    pub width: u32,
+    /// Distance of the lowest bit of the field from the lowest bit of the group
+    pub shift: u32,
+    /// Bytes of the whole group, on its first field only
+    pub group_bytes: Option<usize>,
+}
+
+pub fn field_infos<'a>(
+    fields: &'a Fields,
+    container: &ContainerAttrs,
+) -> syn::Result<Vec<FieldInfo<'a>>> {
+    let mut infos = Vec::with_capacity(fields.len());
+    for (i, field) in fields.iter().enumerate() {
+        let attrs = FieldAttrs::parse(&field.attrs)?;
+        if attrs.len_from.is_some() && attrs.sequence.is_some() {
+            return Err(syn::Error::new_spanned(
+                field,
+                "`count` and `bytes` already mean there is no length prefix, drop `sized`/`splat`/`drain`",
+            ));
+        }
+        if attrs.sequence == Some(Sequence::Sized) && !is_sequence_type(&field.ty) {
+            return Err(syn::Error::new_spanned(
+                &field.ty,
+                "`sized` needs a sequence such as `Vec<T>`, `VecDeque<T>` or `Box<[T]>`",
+            ));
+        }
+        if attrs.fill_len && attrs.len_from.is_none() {
+            return Err(syn::Error::new_spanned(field, "`fill_len` needs `count` or `bytes`"));
+        }
+        let (member, binding) = match &field.ident {
+            Some(ident) => (Member::Named(ident.clone()), ident.clone()),
+            None => (Member::Unnamed(i.into()), format_ident!("field_{i}")),
+        };
+        if attrs.bits.is_some() && attrs.has_non_bits_attrs() {
+            return Err(syn::Error::new_spanned(
+                field,
+                "a `bits` field takes no attributes other than `endian` and `assert`",
+            ));
+        }
+        // the container byte order only reaches numbers, other fields keep their own encoding,
+        // a bitfield group is a number whatever its fields are
+        let endian = attrs.endian.clone().or_else(|| {
+            container
+                .endian
+                .clone()
+                .filter(|_| attrs.bits.is_some() || is_plain_number(&field.ty))
+        });
+        infos.push(FieldInfo {
+            field,
+            attrs,
+            member,
+            binding,
+            endian,
+            bit_slot: None,
+        });
+    }
+    place_bit_fields(&mut infos, container.bit_order)?;
+    Ok(infos)
+}
+
+/// Group runs of consecutive `bits` fields and work out the slot of each field in its group
+fn place_bit_fields(infos: &mut [FieldInfo], bit_order: BitOrder) -> syn::Result<()> {
+    let mut start = 0;
+    while start < infos.len() {
+        let len = infos[start..]
+            .iter()
+            .take_while(|info| info.attrs.bits.is_some())
+            .count();
+        if len == 0 {
+            start += 1;
+            continue;
+        }
+        let group = &mut infos[start..start + len];
+        let total: u32 = group.iter().filter_map(|info| info.attrs.bits).sum();
+        if total % 8 != 0 || total > 128 {
+            return Err(syn::Error::new_spanned(
+                group[len - 1].field,
+                format!("a bitfield group must fill whole bytes up to 128 bits, this one has {total}"),
+            ));
+        }
+        if let Some(info) = group[1..].iter().find(|info| info.attrs.endian.is_some()) {
+            return Err(syn::Error::new_spanned(
+                info.field,
+                "the byte order of a bitfield group goes on its first field",
+            ));
+        }
+        let mut used = 0;
+        for (i, info) in group.iter_mut().enumerate() {
+            let width = info.attrs.bits.unwrap_or_default();
+            let shift = match bit_order {
+                BitOrder::Msb => total - used - width,
+                BitOrder::Lsb => used,
+            };
+            used += width;
+            info.bit_slot = Some(BitSlot {
+                width,
+                shift,
+                group_bytes: (i == 0).then_some(total as usize / 8),
+            });
+        }
+        start += len;
+    }
+    Ok(())
+}
+
+/// The fields of the bitfield group starting at `index`
+pub fn bit_group<'i, 'a>(infos: &'i [FieldInfo<'a>], index: usize) -> &'i [FieldInfo<'a>] {
+    let len = infos[index..]
+        .iter()
+        .take_while(|info| info.bit_slot.is_some())
+        .count();
+    &infos[index..index + len]
+}
+
+const NUM_TYPES: [&str; 12] = [
+    "u8", "u16", "u32", "u64", "u128", "i8", "i16", "i32", "i64", "i128", "f32", "f64",
+];
+
+/// Whether `ty` is spelled as a primitive integer or float, or an array of them
+fn is_plain_number(ty: &Type) -> bool {
+    match ty {
+        Type::Path(type_path) => {
+            type_path.qself.is_none()
+                && NUM_TYPES.iter().any(|num_type| type_path.path.is_ident(num_type))
+        }
+        Type::Array(array) => is_plain_number(&array.elem),
+        Type::Group(group) => is_plain_number(&group.elem),
+        _ => false,
+    }
+}
+
+/// Whether `ty` is spelled as a primitive integer, float, `bool` or `char`
+fn is_scalar(ty: &Type) -> bool {
+    match ty {
+        Type::Path(type_path) => {
+            type_path.qself.is_none()
+                && NUM_TYPES
+                    .iter()
+                    .chain(&["bool", "char"])
+                    .any(|scalar_type| type_path.path.is_ident(scalar_type))
+        }
+        Type::Group(group) => is_scalar(&group.elem),
+        _ => false,
+    }
+}
+
+/// Fail unless a field that unpacks the rest of the input is the last one.
+///
+/// Only unpacking needs this, packing a `SplatDrain::Splat` in the middle is fine.
+pub fn check_drain_last(infos: &[FieldInfo]) -> syn::Result<()> {
+    let Some((_, leading)) = infos.split_last() else {
+        return Ok(());
+    };
+    match leading.iter().find(|info| {
+        info.attrs.sequence == Some(Sequence::Drain) || is_drain_type(&info.field.ty)
+    }) {
+        Some(info) => Err(syn::Error::new_spanned(
+            info.field,
+            "a drain field takes the rest of the input and must be the last field",
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Whether `ty` is spelled as `DrainVec<T>` or `SplatDrain<T>`, which unpack the rest of the input.
+///
+/// Only the spelling is visible to a derive, a drain behind a type alias or inside another type
+/// is not caught here and unpacks the rest of the input wherever it sits.
+fn is_drain_type(ty: &Type) -> bool {
+    match ty {
+        Type::Path(type_path) => type_path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "DrainVec" || segment.ident == "SplatDrain"),
+        Type::Group(group) => is_drain_type(&group.elem),
+        _ => false,
+    }
+}
+
+/// Byte order expression a field is packed with, `None` for the type's own encoding.
+///
+/// An explicit field attribute wins, then the runtime `__endian` context if one is active,
+/// then the container attribute.
+pub fn field_endian(krate: &syn::Path, info: &FieldInfo, in_context: bool) -> Option<TokenStream> {
+    match &info.attrs.endian {
+        Some(endian) => Some(quote!(#krate::base::Endian::#endian)),
+        None if in_context => Some(quote!(__endian)),
+        None => info
+            .endian
+            .as_ref()
+            .map(|endian| quote!(#krate::base::Endian::#endian)),
+    }
+}
+
+/// Whether `ty` is spelled as `Vec<T>`, `VecDeque<T>` or `Box<[T]>`
+fn is_sequence_type(ty: &Type) -> bool {
+    match ty {
+        Type::Path(type_path) => type_path.path.segments.last().is_some_and(|segment| {
+            match (segment.ident.to_string().as_str(), &segment.arguments) {
+                ("Vec" | "VecDeque", PathArguments::AngleBracketed(args)) => args.args.len() == 1,
+                ("Box", PathArguments::AngleBracketed(args)) => {
+                    matches!(args.args.first(), Some(GenericArgument::Type(Type::Slice(_))))
+                }
+                _ => false,
+            }
+        }),
+        Type::Group(group) => is_sequence_type(&group.elem),
+        _ => false,
+    }
+}
+
+/// Element type of a `Vec<T>`, `VecDeque<T>` or `Box<[T]>` field,
+/// needed where the element type cannot be inferred from the field type
+pub fn sequence_elem_type(ty: &Type) -> syn::Result<&Type> {
+    let elem_type = match ty {
+        Type::Path(type_path) => type_path.path.segments.last().and_then(|segment| {
+            match &segment.arguments {
+                PathArguments::AngleBracketed(args) if args.args.len() == 1 => {
+                    match &args.args[0] {
+                        GenericArgument::Type(Type::Slice(slice)) => Some(&*slice.elem),
+                        GenericArgument::Type(elem_type) => Some(elem_type),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            }
+        }),
+        Type::Group(group) => return sequence_elem_type(&group.elem),
+        _ => None,
+    };
+    elem_type.ok_or_else(|| {
+        syn::Error::new_spanned(ty, "expected a sequence such as `Vec<T>`, `VecDeque<T>` or `Box<[T]>`")
+    })
+}
+
+/// The field `member` refers to, which must come before the field at `index`
+pub fn preceding_field<'i, 'a>(
+    infos: &'i [FieldInfo<'a>],
+    index: usize,
+    member: &Member,
+) -> syn::Result<&'i FieldInfo<'a>> {
+    infos[..index]
+        .iter()
+        .find(|info| &info.member == member)
+        .ok_or_else(|| syn::Error::new_spanned(member, "expected the name of an earlier field"))
+}
+
+/// Bind `__start`, the offset the `align` of the fields counts from,
+/// where a struct or variant begins if any of its fields aligns
+pub fn align_origin(infos: &[FieldInfo]) -> TokenStream {
+    match infos.iter().any(|info| info.attrs.align.is_some()) {
+        true => quote!(
+            let __start = __offset;
+        ),
+        false => quote!(),
+    }
+}
+
+/// `expr` as a block expression evaluated for the field at `index`.
+///
+/// Earlier fields are in scope by their bindings: numbers, `bool` and `char` by value,
+/// everything else as the references produced by `accessor`.
+pub fn scoped_expr(
+    infos: &[FieldInfo],
+    index: usize,
+    expr: impl ToTokens,
+    accessor: impl Fn(&FieldInfo) -> TokenStream,
+) -> TokenStream {
+    let earlier_fields = infos[..index].iter().map(|info| {
+        let binding = &info.binding;
+        let value = accessor(info);
+        let value = match is_scalar(&info.field.ty) {
+            true => quote!(*#value),
+            false => value,
+        };
+        quote!(
+            #[allow(unused_variables)]
+            let #binding = #value;
+        )
+    });
+    quote!({
+        #(#earlier_fields)*
+        #expr
+    })
+}
+
+/// The `if` condition of the field at `index` as a block expression, if it has one
+pub fn field_condition(
+    infos: &[FieldInfo],
+    index: usize,
+    accessor: impl Fn(&FieldInfo) -> TokenStream,
+) -> Option<TokenStream> {
+    let condition = infos[index].attrs.present_if.as_ref()?;
+    Some(scoped_expr(infos, index, condition, accessor))
+}
+
+/// The `assert` of the field at `index` as a block expression, with the field and its own value in scope,
+/// along with the field name and the assertion text for the error
+pub fn field_assertion(
+    infos: &[FieldInfo],
+    index: usize,
+    accessor: impl Fn(&FieldInfo) -> TokenStream,
+) -> Option<(TokenStream, String, String)> {
+    let info = &infos[index];
+    let assertion = info.attrs.assert.as_ref()?;
+    let check = scoped_expr(infos, index + 1, &assertion.expr, accessor);
+    let field = match &info.member {
+        Member::Named(ident) => ident.to_string(),
+        Member::Unnamed(index) => index.index.to_string(),
+    };
+    Some((check, field, assertion.text.clone()))
+}
+
+/// The `count` or `bytes` of the field at `index` as an `Option<usize>` expression, if it has one
+pub fn field_len_from(
+    krate: &syn::Path,
+    infos: &[FieldInfo],
+    index: usize,
+    accessor: impl Fn(&FieldInfo) -> TokenStream,
+) -> Option<TokenStream> {
+    let len_from = infos[index].attrs.len_from.as_ref()?;
+    Some(scoped_expr(infos, index, checked_len(krate, len_from.expr()), accessor))
+}
+
+/// `expr` as an `Option<usize>` expression, with `+`, `-`, `*`, `/` and `%` done in checked `usize`
+/// arithmetic so a length computed from untrusted fields is `None` instead of a panic
+fn checked_len(krate: &syn::Path, expr: &Expr) -> TokenStream {
+    match expr {
+        Expr::Paren(paren) => checked_len(krate, &paren.expr),
+        Expr::Group(group) => checked_len(krate, &group.expr),
+        Expr::Binary(binary) => {
+            let method = match binary.op {
+                BinOp::Add(_) => quote!(checked_add),
+                BinOp::Sub(_) => quote!(checked_sub),
+                BinOp::Mul(_) => quote!(checked_mul),
+                BinOp::Div(_) => quote!(checked_div),
+                BinOp::Rem(_) => quote!(checked_rem),
+                _ => return len_value(krate, expr),
+            };
+            let left = checked_len(krate, &binary.left);
+            let right = checked_len(krate, &binary.right);
+            quote!(::core::option::Option::and_then(#left, |__left: usize| {
+                ::core::option::Option::and_then(#right, |__right: usize| __left.#method(__right))
+            }))
+        }
+        Expr::Lit(ExprLit { lit: Lit::Int(int), .. }) if int.suffix().is_empty() => {
+            quote!(::core::option::Option::Some::<usize>(#int))
+        }
+        _ => len_value(krate, expr),
+    }
+}
+
+/// A single value of a length expression as an `Option<usize>`
+fn len_value(krate: &syn::Path, expr: &Expr) -> TokenStream {
+    // method call syntax so the length can be a reference to a field or a computed value
+    quote!({
+        use #krate::base::LengthPrefix as _;
+        (#expr).to_len()
+    })
+}
+
+/// Length of the sequence `field` in the unit of its `count` or `bytes` attribute
+pub fn sequence_len(krate: &syn::Path, len_from: &LenFrom, field: &TokenStream) -> TokenStream {
+    match len_from {
+        LenFrom::Count(_) => quote!((#field).len()),
+        LenFrom::Bytes(_) => quote!(
+            ::core::iter::Iterator::sum::<usize>(::core::iter::Iterator::map(
+                (#field).iter(),
+                #krate::base::ByteSize::byte_size,
+            ))
+        ),
+    }
+}
+
+/// The earlier field a `fill_len` field at `index` takes its length from,
+/// its `count` or `bytes` must name that field
+pub fn fill_len_field<'i, 'a>(infos: &'i [FieldInfo<'a>], index: usize) -> syn::Result<&'i FieldInfo<'a>> {
+    let Some(len_from) = &infos[index].attrs.len_from else {
+        unreachable!("fill_len is only accepted together with count or bytes");
+    };
+    let expr = len_from.expr();
+    let Expr::Path(path) = expr else {
+        return Err(syn::Error::new_spanned(expr, "`fill_len` needs the name of an earlier field"));
+    };
+    infos[..index]
+        .iter()
+        .find(|info| path.qself.is_none() && path.path.is_ident(&info.binding))
+        .ok_or_else(|| syn::Error::new_spanned(expr, "expected the name of an earlier field"))
+}
+
+/// The later field whose length is filled into the field at `index`, if any
+pub fn len_filled_by<'i, 'a>(
+    infos: &'i [FieldInfo<'a>],
+    index: usize,
+) -> syn::Result<Option<&'i FieldInfo<'a>>> {
+    for (i, info) in infos.iter().enumerate().skip(index + 1) {
+        if info.attrs.fill_len && fill_len_field(infos, i)?.binding == infos[index].binding {
+            return Ok(Some(info));
+        }
+    }
+    Ok(None)
+}
+
+/// The later field whose tag is filled into the field at `index`, if any
+pub fn tag_filled_by<'i, 'a>(infos: &'i [FieldInfo<'a>], index: usize) -> Option<&'i FieldInfo<'a>> {
+    infos[index + 1..].iter().find(|info| {
+        info.attrs.fill_tag && info.attrs.tag_from.as_ref() == Some(&infos[index].member)
+    })
+}
+
+/// `path { a, b }`, `path(field_0, field_1)` or `path`,
+/// usable both as a constructor and as a pattern binding every field
+pub fn bind_fields(path: TokenStream, fields: &Fields, infos: &[FieldInfo]) -> TokenStream {
+    let bindings = infos.iter().map(|info| &info.binding);
+    match fields {
+        Fields::Named(_) => quote!(#path { #(#bindings),* }),
+        Fields::Unnamed(_) => quote!(#path(#(#bindings),*)),
+        Fields::Unit => quote!(#path),
+    }
+}
+
+pub struct EnumTags {
+    pub tag_type: Type,
+    /// Tag value of each variant, in declaration order
+    pub values: Vec<Expr>,
+}
+
+/// Resolve the tag type and the tag of every variant.
+///
+/// The tag type comes from `#[bytepack(tag_type = "..")]`, then `#[repr(..)]`, then defaults to `u8`.
+/// A variant tag comes from `#[bytepack(tag = ..)]`, then the Rust discriminant,
+/// then is the previous tag plus one, starting from zero.
+pub fn enum_tags(ast: &DeriveInput, data: &DataEnum) -> syn::Result<EnumTags> {
+    let container = ContainerAttrs::parse(&ast.attrs)?;
+    if container.magic.is_some() {
+        return Err(syn::Error::new_spanned(&ast.ident, "`magic` is only supported on structs"));
+    }
+    if container.align.is_some() || container.pad_to.is_some() {
+        return Err(syn::Error::new_spanned(
+            &ast.ident,
+            "`align` and `pad_to` on an enum are not supported, put them on its fields",
+        ));
+    }
+    let tag_type = match container.tag_type {
+        Some(tag_type) => tag_type,
+        None => repr_int_type(&ast.attrs)?.unwrap_or_else(|| syn::parse_quote!(u8)),
+    };
+
+    let mut values = Vec::with_capacity(data.variants.len());
+    let mut next: Expr = syn::parse_quote!(0);
+    for variant in &data.variants {
+        let attrs = VariantAttrs::parse(&variant.attrs)?;
+        let value = match (attrs.tag, &variant.discriminant) {
+            (Some(tag), _) => tag,
+            (None, Some((_, discriminant))) => discriminant.clone(),
+            (None, None) => next,
+        };
+        next = syn::parse_quote!((#value) + 1);
+        values.push(value);
+    }
+
+    Ok(EnumTags { tag_type, values })
+}
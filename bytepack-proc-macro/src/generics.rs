@@ -0,0 +1,23 @@
+use proc_macro2::TokenStream;
+use syn::{Generics, WherePredicate};
+
+/// Generics for a derived impl where every type parameter is bounded by `bound`,
+/// or by the user's `#[bytepack(bound = "..")]` predicates instead when given
+pub fn bounded_generics(
+    generics: &Generics,
+    bound: TokenStream,
+    user_bound: &Option<Vec<WherePredicate>>,
+) -> Generics {
+    let mut generics = generics.clone();
+    let type_params: Vec<_> = generics.type_params().map(|param| param.ident.clone()).collect();
+    let where_clause = generics.make_where_clause();
+    match user_bound {
+        Some(predicates) => where_clause.predicates.extend(predicates.iter().cloned()),
+        None => {
+            for ident in type_params {
+                where_clause.predicates.push(syn::parse_quote!(#ident: #bound));
+            }
+        }
+    }
+    generics
+}
@@ -3,34 +3,64 @@ extern crate proc_macro;
 use proc_macro::TokenStream;
 use syn::{parse_macro_input, DeriveInput};
 
+mod attr;
+mod bitfield;
+mod fields;
+mod generics;
 mod pack;
 mod size;
 mod unpack;
 
-#[proc_macro_derive(ConstByteSize)]
+/// The error for a derive applied to a union, which has no layout to pack
+fn union_error(ast: &DeriveInput) -> syn::Error {
+    syn::Error::new_spanned(&ast.ident, "bytepack derives are not supported for unions")
+}
+
+#[proc_macro_derive(ConstByteSize, attributes(bytepack))]
 pub fn constbytesize_derive(input: TokenStream) -> TokenStream {
     let ast = parse_macro_input!(input as DeriveInput);
 
     size::impl_constbytesize(&ast)
 }
 
-#[proc_macro_derive(ByteSize)]
+#[proc_macro_derive(ByteSize, attributes(bytepack))]
 pub fn bytesize_derive(input: TokenStream) -> TokenStream {
     let ast = parse_macro_input!(input as DeriveInput);
 
     size::impl_bytesize(&ast)
 }
 
-#[proc_macro_derive(BytePack)]
+#[proc_macro_derive(BytePack, attributes(bytepack))]
 pub fn bytepack_derive(input: TokenStream) -> TokenStream {
     let ast = parse_macro_input!(input as DeriveInput);
 
     pack::impl_bytepack(&ast)
 }
 
-#[proc_macro_derive(ByteUnpack)]
+#[proc_macro_derive(ByteUnpack, attributes(bytepack))]
 pub fn byteunpack_derive(input: TokenStream) -> TokenStream {
     let ast = parse_macro_input!(input as DeriveInput);
 
     unpack::impl_byteunpack(&ast)
 }
+
+#[proc_macro_derive(BytePackEndian, attributes(bytepack))]
+pub fn bytepack_endian_derive(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+
+    pack::impl_bytepack_endian(&ast)
+}
+
+#[proc_macro_derive(ByteUnpackEndian, attributes(bytepack))]
+pub fn byteunpack_endian_derive(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+
+    unpack::impl_byteunpack_endian(&ast)
+}
+
+#[proc_macro_derive(BitField, attributes(bytepack))]
+pub fn bitfield_derive(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+
+    bitfield::impl_bitfield(&ast)
+}
@@ -1,87 +1,522 @@
 use proc_macro::TokenStream;
-use quote::{format_ident, quote};
-use syn::{parse_macro_input, DeriveInput};
+use quote::quote;
+use syn::{DeriveInput, Path};
+
+use crate::attr::ContainerAttrs;
+use crate::fields::{
+    align_origin, bind_fields, bit_group, enum_tags, field_assertion, field_condition, field_endian,
+    field_infos, field_len_from, len_filled_by, preceding_field, sequence_len, tag_filled_by,
+    FieldInfo,
+};
+use crate::generics::bounded_generics;
+use crate::union_error;
 
 pub fn impl_bytepack(ast: &DeriveInput) -> TokenStream {
+    match bytepack(ast) {
+        Ok(gen) => gen.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+pub fn impl_bytepack_endian(ast: &DeriveInput) -> TokenStream {
+    match bytepack_endian(ast) {
+        Ok(gen) => gen.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn bytepack(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
     let name = &ast.ident;
+    let container = ContainerAttrs::parse(&ast.attrs)?;
+    let krate = &container.crate_path();
+    let generics = bounded_generics(
+        &ast.generics,
+        quote!(#krate::pack::BytePack),
+        &container.bounds.pack,
+    );
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
     match &ast.data {
         syn::Data::Struct(data) => {
-            let mut fields_bytepack = quote!();
-            match &data.fields {
-                syn::Fields::Named(fields) => {
-                    for field in fields.named.iter() {
-                        let field_name = field.ident.as_ref().unwrap();
-                        fields_bytepack.extend(quote!(
-                            let _res = BytePack::pack(&self.#field_name, buf)?;
-                            let byte_size = ByteSize::byte_size(&self.#field_name);
-                            let buf = &mut buf[byte_size..];
-                        ));
+            let infos = field_infos(&data.fields, &container)?;
+            let fields_bytepack = pack_fields(krate, &infos, false, |info| {
+                let member = &info.member;
+                quote!(&self.#member)
+            })?;
+            // the fields align relative to the start of the struct, in front of its magic
+            let start = align_origin(&infos);
+            let magic_bytepack = pack_container_magic(krate, &container);
+            let padding_bytepack = pack_container_padding(krate, &container);
+            Ok(quote! {
+                impl #impl_generics #krate::pack::BytePack for #name #ty_generics #where_clause {
+                    fn pack(
+                        &self,
+                        __buf: &mut [u8],
+                    ) -> ::core::result::Result<usize, #krate::pack::PackError> {
+                        let __offset = 0;
+                        #start
+                        #magic_bytepack
+                        #fields_bytepack
+                        #padding_bytepack
+                        ::core::result::Result::Ok(__offset)
+                    }
+                }
+            })
+        },
+        syn::Data::Enum(data) => {
+            let tags = enum_tags(ast, data)?;
+            let tag_type = &tags.tag_type;
+            let variants_bytepack = pack_variants(krate, &container, data, false)?;
+            Ok(quote! {
+                impl #impl_generics #krate::pack::UntaggedBytePack for #name #ty_generics #where_clause {
+                    fn pack_untagged(
+                        &self,
+                        __buf: &mut [u8],
+                    ) -> ::core::result::Result<usize, #krate::pack::PackError> {
+                        let __offset = 0;
+                        match self {
+                            #variants_bytepack
+                        }
+                    }
+                }
+
+                impl #impl_generics #krate::pack::BytePack for #name #ty_generics #where_clause {
+                    fn pack(
+                        &self,
+                        __buf: &mut [u8],
+                    ) -> ::core::result::Result<usize, #krate::pack::PackError> {
+                        let __tag: #tag_type = #krate::base::Tagged::tag(self);
+                        let __offset = #krate::pack::BytePack::pack(&__tag, __buf)?;
+                        let __size = #krate::pack::UntaggedBytePack::pack_untagged(
+                            self,
+                            #krate::pack::pack_rest(__buf, __offset)?,
+                        )
+                        .map_err(|e| e.shifted(__offset))?;
+                        ::core::result::Result::Ok(__offset + __size)
                     }
                 }
-                syn::Fields::Unnamed(_) => unimplemented!(),
-                syn::Fields::Unit => {},
+            })
+        },
+        syn::Data::Union(_) => Err(union_error(ast)),
+    }
+}
+
+fn bytepack_endian(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &ast.ident;
+    let container = ContainerAttrs::parse(&ast.attrs)?;
+    let krate = &container.crate_path();
+    let generics = bounded_generics(
+        &ast.generics,
+        quote!(#krate::pack::BytePackEndian),
+        &container.bounds.pack,
+    );
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let mut untagged_impl = quote!();
+    let body = match &ast.data {
+        syn::Data::Struct(data) => {
+            let infos = field_infos(&data.fields, &container)?;
+            let fields_bytepack = pack_fields(krate, &infos, true, |info| {
+                let member = &info.member;
+                quote!(&self.#member)
+            })?;
+            // the fields align relative to the start of the struct, in front of its magic
+            let start = align_origin(&infos);
+            let magic_bytepack = pack_container_magic(krate, &container);
+            let padding_bytepack = pack_container_padding(krate, &container);
+            quote! {
+                let __offset = 0;
+                #start
+                #magic_bytepack
+                #fields_bytepack
+                #padding_bytepack
+                ::core::result::Result::Ok(__offset)
             }
-            let gen = quote! {
-                impl BytePack for #name {
-                    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
-                        #fields_bytepack
-                        Ok(())
+        },
+        syn::Data::Enum(data) => {
+            let tags = enum_tags(ast, data)?;
+            let tag_type = &tags.tag_type;
+            let variants_bytepack = pack_variants(krate, &container, data, true)?;
+            untagged_impl = quote! {
+                impl #impl_generics #krate::pack::UntaggedBytePackEndian for #name #ty_generics #where_clause {
+                    fn pack_untagged_endian(
+                        &self,
+                        __buf: &mut [u8],
+                        __endian: #krate::base::Endian,
+                    ) -> ::core::result::Result<usize, #krate::pack::PackError> {
+                        let __offset = 0;
+                        match self {
+                            #variants_bytepack
+                        }
                     }
                 }
             };
-            gen.into()
+            quote! {
+                let __tag: #tag_type = #krate::base::Tagged::tag(self);
+                let __offset = #krate::pack::BytePackEndian::pack_endian(&__tag, __buf, __endian)?;
+                match self {
+                    #variants_bytepack
+                }
+            }
         },
-        syn::Data::Enum(_) => unimplemented!(),
-        // syn::Data::Enum(data) => {
-        //     let mut enum_variants_into_bson = quote!();
-        //     for variant in &data.variants {
-        //         let variant_name = &variant.ident;
-        //         match variant.fields.len() {
-        //             0 => {
-        //                 enum_variants_into_bson.extend(quote!(
-        //                     Self::#variant_name => bson::Bson::String(stringify!(#variant_name).to_string()),
-        //                 ));
-        //             }
-        //             1 => {
-        //                 enum_variants_into_bson.extend(quote!(
-        //                     Self::#variant_name(val) => bson::Bson::Document(bson::doc!(
-        //                         stringify!(#variant_name): IntoBson::into_bson(val),
-        //                     )),
-        //                 ));
-        //             }
-        //             n => {
-        //                 let mut destructure_quote = quote!();
-        //                 let mut bson_vec_quote = quote!();
-        //                 destructure_quote.extend(
-        //                     (0..n)
-        //                         .map(|i| format_ident!("val_{i}"))
-        //                         .map(|val_i| quote!(#val_i,)),
-        //                 );
-        //                 bson_vec_quote.extend(
-        //                     (0..n)
-        //                         .map(|i| format_ident!("val_{i}"))
-        //                         .map(|val_i| quote!(IntoBson::into_bson(#val_i),)),
-        //                 );
-
-        //                 enum_variants_into_bson.extend(quote!(
-        //                     Self::#variant_name(#destructure_quote) => bson::Bson::Document(bson::doc!(
-        //                         stringify!(#variant_name): IntoBson::into_bson(vec![#bson_vec_quote]),
-        //                     )),
-        //                 ));
-        //             } // _ => panic!("Only implemented for unit or tuple kinded variants"),
-        //         }
-        //     }
-        //     let gen = quote! {
-        //         impl IntoBson for #name {
-        //             fn into_bson(self) -> bson::Bson {
-        //                 match self {
-        //                     #enum_variants_into_bson
-        //                 }
-        //             }
-        //         }
-        //     };
-        //     gen.into()
-        // }
-        syn::Data::Union(_) => unimplemented!(),
+        syn::Data::Union(_) => return Err(union_error(ast)),
+    };
+    Ok(quote! {
+        #untagged_impl
+
+        impl #impl_generics #krate::pack::BytePackEndian for #name #ty_generics #where_clause {
+            fn pack_endian(
+                &self,
+                __buf: &mut [u8],
+                __endian: #krate::base::Endian,
+            ) -> ::core::result::Result<usize, #krate::pack::PackError> {
+                #body
+            }
+        }
+    })
+}
+
+/// Match arms packing the fields of each variant, starting at `__offset`
+fn pack_variants(
+    krate: &Path,
+    container: &ContainerAttrs,
+    data: &syn::DataEnum,
+    in_context: bool,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let mut variants_bytepack = quote!();
+    for variant in data.variants.iter() {
+        let variant_name = &variant.ident;
+        let infos = field_infos(&variant.fields, container)?;
+        let pattern = bind_fields(quote!(Self::#variant_name), &variant.fields, &infos);
+        let start = align_origin(&infos);
+        let fields_bytepack = pack_fields(krate, &infos, in_context, |info| {
+            let binding = &info.binding;
+            quote!(#binding)
+        })?;
+        variants_bytepack.extend(quote! {
+            #pattern => {
+                #start
+                #fields_bytepack
+                ::core::result::Result::Ok(__offset)
+            }
+        });
+    }
+    Ok(variants_bytepack)
+}
+
+/// Pack each field in order, advancing `__offset` by the bytes written.
+///
+/// With `in_context` the fields follow the runtime `__endian`,
+/// which a `byte_order_mark` field also switches on for the fields after it.
+fn pack_fields(
+    krate: &Path,
+    infos: &[FieldInfo],
+    mut in_context: bool,
+    accessor: impl Fn(&FieldInfo) -> proc_macro2::TokenStream,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let mut fields_bytepack = quote!();
+    for (i, info) in infos.iter().enumerate() {
+        let field = accessor(info);
+        let field_type = &info.field.ty;
+        let endian = field_endian(krate, info, in_context);
+        if let Some(slot) = &info.bit_slot {
+            // the first field packs the whole group
+            if let Some(group_bytes) = slot.group_bytes {
+                fields_bytepack.extend(pack_bit_group(krate, infos, i, &accessor, endian, group_bytes));
+            }
+            continue;
+        }
+        if let Some(align) = info.attrs.align {
+            fields_bytepack.extend(pack_align(krate, quote!(__start), align));
+        }
+        if info.attrs.pad_to.is_some() {
+            fields_bytepack.extend(quote!(
+                let __field_start = __offset;
+            ));
+        }
+        if let Some(body) = tag_filled_by(infos, i) {
+            let body = accessor(body);
+            let pack_tag = pack_value(krate, quote!(&__tag), endian);
+            fields_bytepack.extend(quote!(
+                let __expected =
+                    #krate::base::Discriminant::to_discriminant(&#krate::base::Tagged::tag(#body));
+                let __tag =
+                    <#field_type as #krate::base::Discriminant>::try_from_discriminant(__expected)
+                        .ok_or(#krate::pack::PackError::TagMismatch {
+                            offset: __offset,
+                            expected: __expected,
+                            found: #krate::base::Discriminant::to_discriminant(
+                                &<#field_type as #krate::base::Discriminant>::from_discriminant(__expected),
+                            ),
+                        })?;
+                let __size = #pack_tag;
+            ));
+        } else if let Some(sequence) = len_filled_by(infos, i)? {
+            let Some(len_from) = &sequence.attrs.len_from else {
+                unreachable!("only a field with count or bytes fills a length");
+            };
+            let len = sequence_len(krate, len_from, &accessor(sequence));
+            let pack_len = pack_value(krate, quote!(&__len), endian);
+            fields_bytepack.extend(quote!(
+                let __len = #len;
+                let __len = <#field_type as #krate::base::LengthPrefix>::from_len(__len).ok_or(
+                    #krate::pack::PackError::LengthOverflow { offset: __offset, len: __len },
+                )?;
+                let __size = #pack_len;
+            ));
+        } else if let Some(member) = &info.attrs.tag_from {
+            let header = preceding_field(infos, i, member)?;
+            if !info.attrs.fill_tag {
+                let header = accessor(header);
+                fields_bytepack.extend(quote!(
+                    let __expected = #krate::base::Discriminant::to_discriminant(
+                        &#krate::base::Tagged::tag(#field),
+                    );
+                    let __found = #krate::base::Discriminant::to_discriminant(#header);
+                    if __expected != __found {
+                        return ::core::result::Result::Err(#krate::pack::PackError::TagMismatch {
+                            offset: __offset,
+                            expected: __expected,
+                            found: __found,
+                        });
+                    }
+                ));
+            }
+            let pack_untagged = match endian {
+                Some(endian) => quote!(
+                    #krate::pack::UntaggedBytePackEndian::pack_untagged_endian(
+                        #field,
+                        #krate::pack::pack_rest(__buf, __offset)?,
+                        #endian,
+                    )
+                ),
+                None => quote!(
+                    #krate::pack::UntaggedBytePack::pack_untagged(
+                        #field,
+                        #krate::pack::pack_rest(__buf, __offset)?,
+                    )
+                ),
+            };
+            fields_bytepack.extend(quote!(
+                let __size = #pack_untagged.map_err(|e| e.shifted(__offset))?;
+            ));
+        } else if let Some(condition) = field_condition(infos, i, &accessor) {
+            let pack_field = pack_value(krate, quote!(__value), endian);
+            fields_bytepack.extend(quote!(
+                let __size = match (#field, #condition) {
+                    (::core::option::Option::Some(__value), true) => #pack_field,
+                    (::core::option::Option::None, false) => 0,
+                    _ => {
+                        return ::core::result::Result::Err(
+                            #krate::pack::PackError::ConditionMismatch { offset: __offset },
+                        );
+                    }
+                };
+            ));
+        } else if info.attrs.is_unprefixed_sequence() {
+            if let (Some(len_from), false) = (&info.attrs.len_from, info.attrs.fill_len) {
+                let len = sequence_len(krate, len_from, &field);
+                let found = field_len_from(krate, infos, i, &accessor);
+                fields_bytepack.extend(quote!(
+                    let __len = #len;
+                    if #found != ::core::option::Option::Some(__len) {
+                        return ::core::result::Result::Err(
+                            #krate::pack::PackError::LengthMismatch { offset: __offset, len: __len },
+                        );
+                    }
+                ));
+            }
+            let pack_splat = match endian {
+                Some(endian) => quote!(
+                    #krate::pack::pack_splat_endian(
+                        (#field).iter(),
+                        #krate::pack::pack_rest(__buf, __offset)?,
+                        #endian,
+                    )
+                ),
+                None => quote!(
+                    #krate::pack::pack_splat(
+                        (#field).iter(),
+                        #krate::pack::pack_rest(__buf, __offset)?,
+                    )
+                ),
+            };
+            fields_bytepack.extend(quote!(
+                let __size = #pack_splat.map_err(|e| e.shifted(__offset))?;
+            ));
+        } else if let Some(len_type) = &info.attrs.len_type {
+            let pack_sized = match endian {
+                Some(endian) => quote!(
+                    #krate::pack::pack_sized_endian::<_, #len_type>(
+                        (#field).iter(),
+                        #krate::pack::pack_rest(__buf, __offset)?,
+                        #endian,
+                    )
+                ),
+                None => quote!(
+                    #krate::pack::pack_sized::<_, #len_type>(
+                        (#field).iter(),
+                        #krate::pack::pack_rest(__buf, __offset)?,
+                    )
+                ),
+            };
+            fields_bytepack.extend(quote!(
+                let __size = #pack_sized.map_err(|e| e.shifted(__offset))?;
+            ));
+        } else {
+            let pack_field = pack_value(krate, field.clone(), endian);
+            fields_bytepack.extend(quote!(
+                let __size = #pack_field;
+            ));
+        }
+        fields_bytepack.extend(pack_assertion(krate, infos, i, &accessor));
+        if info.attrs.byte_order_mark {
+            fields_bytepack.extend(quote!(
+                let __endian = #krate::base::ByteOrderMark::endian(#field).ok_or(
+                    #krate::pack::PackError::UnknownByteOrder { offset: __offset },
+                )?;
+            ));
+            in_context = true;
+        }
+        fields_bytepack.extend(quote!(
+            let __offset = __offset + __size;
+        ));
+        if let Some(pad_to) = info.attrs.pad_to {
+            fields_bytepack.extend(pack_pad_to(krate, quote!(__field_start), pad_to));
+        }
+    }
+    Ok(fields_bytepack)
+}
+
+/// Merge the fields of the bitfield group at `index` into one integer and pack its `group_bytes` low bytes
+fn pack_bit_group(
+    krate: &Path,
+    infos: &[FieldInfo],
+    index: usize,
+    accessor: impl Fn(&FieldInfo) -> proc_macro2::TokenStream,
+    endian: Option<proc_macro2::TokenStream>,
+    group_bytes: usize,
+) -> proc_macro2::TokenStream {
+    let group = bit_group(infos, index);
+    let mut group_bytepack = quote!(
+        let __bits: u128 = 0;
+    );
+    for (i, info) in group.iter().enumerate() {
+        group_bytepack.extend(pack_assertion(krate, infos, index + i, &accessor));
+        let field = accessor(info);
+        let Some(slot) = &info.bit_slot else {
+            unreachable!("every field of a bitfield group has a slot");
+        };
+        let (width, shift) = (slot.width, slot.shift);
+        group_bytepack.extend(quote!(
+            let __bits = __bits
+                | (#krate::pack::pack_bitfield(#field, #width).map_err(|e| e.shifted(__offset))? << #shift);
+        ));
+    }
+    let endian = endian.unwrap_or_else(|| quote!(#krate::base::Endian::Big));
+    group_bytepack.extend(quote!(
+        let __size = #krate::pack::pack_bits(
+            #krate::pack::pack_rest(__buf, __offset)?,
+            __bits,
+            #group_bytes,
+            #endian,
+        )
+        .map_err(|e| e.shifted(__offset))?;
+        let __offset = __offset + __size;
+    ));
+    group_bytepack
+}
+
+/// Fail with `AssertionFailed` unless the field at `index` meets its `assert`
+fn pack_assertion(
+    krate: &Path,
+    infos: &[FieldInfo],
+    index: usize,
+    accessor: impl Fn(&FieldInfo) -> proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let Some((check, field, assertion)) = field_assertion(infos, index, accessor) else {
+        return quote!();
+    };
+    quote!(
+        if !#check {
+            return ::core::result::Result::Err(#krate::pack::PackError::AssertionFailed {
+                offset: __offset,
+                field: #field,
+                assertion: #assertion,
+            });
+        }
+    )
+}
+
+/// The magic bytes in front of the fields of a struct
+fn pack_container_magic(krate: &Path, container: &ContainerAttrs) -> proc_macro2::TokenStream {
+    let Some(magic) = &container.magic else {
+        return quote!();
+    };
+    quote!(
+        let __size = #krate::pack::pack_magic(#krate::pack::pack_rest(__buf, __offset)?, #magic)
+            .map_err(|e| e.shifted(__offset))?;
+        let __offset = __offset + __size;
+    )
+}
+
+/// Trailing padding of a struct, first up to `pad_to` and then to a multiple of `align`
+fn pack_container_padding(krate: &Path, container: &ContainerAttrs) -> proc_macro2::TokenStream {
+    let mut padding_bytepack = quote!();
+    if let Some(pad_to) = container.pad_to {
+        padding_bytepack.extend(pack_pad_to(krate, quote!(0), pad_to));
+    }
+    if let Some(align) = container.align {
+        padding_bytepack.extend(pack_align(krate, quote!(0), align));
+    }
+    padding_bytepack
+}
+
+/// Zero padding at `__offset` so the bytes since `start` become a multiple of `align`
+fn pack_align(krate: &Path, start: proc_macro2::TokenStream, align: usize) -> proc_macro2::TokenStream {
+    quote!(
+        let __size = #krate::pack::pack_fill(
+            #krate::pack::pack_rest(__buf, __offset)?,
+            #krate::base::align_padding(__offset - #start, #align),
+            0,
+        )
+        .map_err(|e| e.shifted(__offset))?;
+        let __offset = __offset + __size;
+    )
+}
+
+/// Zero padding at `__offset` so the bytes since `start` add up to `pad_to`
+fn pack_pad_to(krate: &Path, start: proc_macro2::TokenStream, pad_to: usize) -> proc_macro2::TokenStream {
+    quote!(
+        let __size = #krate::pack::pack_pad_to(
+            #krate::pack::pack_rest(__buf, __offset)?,
+            __offset - #start,
+            #pad_to,
+        )
+        .map_err(|e| e.shifted(__offset))?;
+        let __offset = __offset + __size;
+    )
+}
+
+/// Pack `value` at `__offset`, in `endian` if given, evaluating to the bytes written
+fn pack_value(
+    krate: &Path,
+    value: proc_macro2::TokenStream,
+    endian: Option<proc_macro2::TokenStream>,
+) -> proc_macro2::TokenStream {
+    match endian {
+        Some(endian) => quote!(
+            #krate::pack::BytePackEndian::pack_endian(
+                #value,
+                #krate::pack::pack_rest(__buf, __offset)?,
+                #endian,
+            )
+            .map_err(|e| e.shifted(__offset))?
+        ),
+        None => quote!(
+            #krate::pack::BytePack::pack(
+                #value,
+                #krate::pack::pack_rest(__buf, __offset)?,
+            )
+            .map_err(|e| e.shifted(__offset))?
+        ),
     }
-}
\ No newline at end of file
+}
@@ -1,115 +1,300 @@
 use proc_macro::TokenStream;
-use quote::{format_ident, quote};
-use syn::{parse_macro_input, DeriveInput};
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{DeriveInput, Path};
+
+use crate::attr::ContainerAttrs;
+use crate::fields::{bind_fields, enum_tags, field_infos, BitSlot, EnumTags, FieldInfo};
+use crate::generics::bounded_generics;
+use crate::union_error;
 
 pub fn impl_constbytesize(ast: &DeriveInput) -> TokenStream {
+    match constbytesize(ast) {
+        Ok(gen) => gen.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+pub fn impl_bytesize(ast: &DeriveInput) -> TokenStream {
+    match bytesize(ast) {
+        Ok(gen) => gen.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn constbytesize(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
     let name = &ast.ident;
-    match &ast.data {
+    let container = ContainerAttrs::parse(&ast.attrs)?;
+    let krate = &container.crate_path();
+    let generics = bounded_generics(
+        &ast.generics,
+        quote!(#krate::base::ConstByteSize),
+        &container.bounds.const_size,
+    );
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let size_in_bytes = match &ast.data {
         syn::Data::Struct(data) => {
-            let mut size_in_bytes = quote!(0);
-            match &data.fields {
-                syn::Fields::Named(fields) => {
-                    for field in fields.named.iter() {
-                        let field_type = &field.ty;
-                        size_in_bytes.extend(quote!(
-                            + <#field_type as ConstByteSize>::const_byte_size()
-                        ));
-                    }
-                }
-                syn::Fields::Unnamed(_) => unimplemented!(),
-                syn::Fields::Unit => {},
+            let infos = field_infos(&data.fields, &container)?;
+            // the fields and their alignment count from the start of the struct, magic included
+            let magic_size = container_magic_size(&container);
+            let mut size_in_bytes = quote!(
+                let __size = #magic_size;
+            );
+            for info in &infos {
+                let field_type = &info.field.ty;
+                let field_size = match (&info.bit_slot, &info.attrs.tag_from) {
+                    (Some(slot), _) => bit_group_size(slot),
+                    // an externally tagged enum occupies its size without the tag, which the
+                    // enum only has as a constant when none of its variants has fields
+                    (None, Some(_)) => quote!(
+                        <#field_type as #krate::base::ConstByteSize>::const_byte_size()
+                            - <<#field_type as #krate::base::Tagged>::Tag as #krate::base::ConstByteSize>::const_byte_size()
+                    ),
+                    (None, None) => quote!(<#field_type as #krate::base::ConstByteSize>::const_byte_size()),
+                };
+                size_in_bytes.extend(add_field_size(krate, info, field_size));
             }
-            let gen = quote! {
-                impl ConstByteSize for #name {
-                    fn const_byte_size() -> usize {
-                        #size_in_bytes
-                    }
-                }
-            };
-            gen.into()
+            let padding_size = add_container_padding(krate, &container);
+            quote!({
+                #size_in_bytes
+                #padding_size
+                __size
+            })
         },
-        syn::Data::Enum(_) => unimplemented!(),
-        syn::Data::Union(_) => unimplemented!(),
-    }
+        syn::Data::Enum(data) => {
+            if let Some(variant) = data.variants.iter().find(|v| !v.fields.is_empty()) {
+                return Err(syn::Error::new_spanned(
+                    variant,
+                    "ConstByteSize can only be derived for enums without fields",
+                ));
+            }
+            let tags = enum_tags(ast, data)?;
+            let tag_type = &tags.tag_type;
+            quote!(<#tag_type as #krate::base::ConstByteSize>::const_byte_size())
+        },
+        syn::Data::Union(_) => return Err(union_error(ast)),
+    };
+    Ok(quote! {
+        impl #impl_generics #krate::base::ConstByteSize for #name #ty_generics #where_clause {
+            fn const_byte_size() -> usize {
+                #size_in_bytes
+            }
+        }
+    })
 }
 
-pub fn impl_bytesize(ast: &DeriveInput) -> TokenStream {
+fn bytesize(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
     let name = &ast.ident;
+    let container = ContainerAttrs::parse(&ast.attrs)?;
+    let krate = &container.crate_path();
+    let generics = bounded_generics(
+        &ast.generics,
+        quote!(#krate::base::ByteSize),
+        &container.bounds.size,
+    );
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
     match &ast.data {
         syn::Data::Struct(data) => {
-            let mut size_in_bytes = quote!(0);
-            match &data.fields {
-                syn::Fields::Named(fields) => {
-                    for field in fields.named.iter() {
-                        let field_name = field.ident.as_ref().unwrap();
-                        size_in_bytes.extend(quote!(
-                            + ByteSize::byte_size(&self.#field_name)
-                        ));
+            let infos = field_infos(&data.fields, &container)?;
+            let magic_size = container_magic_size(&container);
+            let size_in_bytes = sum_field_sizes(krate, &infos, magic_size, |info| {
+                let member = &info.member;
+                quote!(&self.#member)
+            });
+            let padding_size = add_container_padding(krate, &container);
+            Ok(quote! {
+                impl #impl_generics #krate::base::ByteSize for #name #ty_generics #where_clause {
+                    fn byte_size(&self) -> usize {
+                        let __size = #size_in_bytes;
+                        #padding_size
+                        __size
                     }
                 }
-                syn::Fields::Unnamed(_) => unimplemented!(),
-                syn::Fields::Unit => {},
+            })
+        },
+        syn::Data::Enum(data) => {
+            let tags = enum_tags(ast, data)?;
+            let tag_type = &tags.tag_type;
+            let mut variants_tag = quote!();
+            let mut variants_bytesize = quote!();
+            for (variant, tag) in data.variants.iter().zip(&tags.values) {
+                let variant_name = &variant.ident;
+                let infos = field_infos(&variant.fields, &container)?;
+                let pattern = bind_fields(quote!(Self::#variant_name), &variant.fields, &infos);
+                let fields_bytesize = sum_field_sizes(krate, &infos, quote!(0), |info| {
+                    let binding = &info.binding;
+                    quote!(#binding)
+                });
+                variants_tag.extend(quote! {
+                    Self::#variant_name { .. } => #krate::base::Discriminant::from_discriminant((#tag) as i128),
+                });
+                variants_bytesize.extend(quote! {
+                    #pattern => #fields_bytesize,
+                });
             }
-            let gen = quote! {
-                impl ByteSize for #name {
+            let tags_check = check_tags(krate, data, &tags);
+            Ok(quote! {
+                #tags_check
+
+                impl #impl_generics #krate::base::Tagged for #name #ty_generics #where_clause {
+                    type Tag = #tag_type;
+
+                    fn tag(&self) -> Self::Tag {
+                        match self {
+                            #variants_tag
+                        }
+                    }
+
+                    fn untagged_byte_size(&self) -> usize {
+                        match self {
+                            #variants_bytesize
+                        }
+                    }
+                }
+
+                impl #impl_generics #krate::base::ByteSize for #name #ty_generics #where_clause {
                     fn byte_size(&self) -> usize {
-                        #size_in_bytes
+                        <#tag_type as #krate::base::ConstByteSize>::const_byte_size()
+                            + #krate::base::Tagged::untagged_byte_size(self)
                     }
                 }
-            };
-            gen.into()
+            })
         },
-        syn::Data::Enum(_) => unimplemented!(),
-        // syn::Data::Enum(data) => {
-        //     let mut enum_variants_into_bson = quote!();
-        //     for variant in &data.variants {
-        //         let variant_name = &variant.ident;
-        //         match variant.fields.len() {
-        //             0 => {
-        //                 enum_variants_into_bson.extend(quote!(
-        //                     Self::#variant_name => bson::Bson::String(stringify!(#variant_name).to_string()),
-        //                 ));
-        //             }
-        //             1 => {
-        //                 enum_variants_into_bson.extend(quote!(
-        //                     Self::#variant_name(val) => bson::Bson::Document(bson::doc!(
-        //                         stringify!(#variant_name): IntoBson::into_bson(val),
-        //                     )),
-        //                 ));
-        //             }
-        //             n => {
-        //                 let mut destructure_quote = quote!();
-        //                 let mut bson_vec_quote = quote!();
-        //                 destructure_quote.extend(
-        //                     (0..n)
-        //                         .map(|i| format_ident!("val_{i}"))
-        //                         .map(|val_i| quote!(#val_i,)),
-        //                 );
-        //                 bson_vec_quote.extend(
-        //                     (0..n)
-        //                         .map(|i| format_ident!("val_{i}"))
-        //                         .map(|val_i| quote!(IntoBson::into_bson(#val_i),)),
-        //                 );
+        syn::Data::Union(_) => Err(union_error(ast)),
+    }
+}
+
+/// Compile time assertions that every tag fits the tag type and no two variants share a tag
+fn check_tags(krate: &Path, data: &syn::DataEnum, tags: &EnumTags) -> proc_macro2::TokenStream {
+    let tag_type = &tags.tag_type;
+    let values = &tags.values;
+    let fits = data.variants.iter().zip(values).map(|(variant, tag)| {
+        let message = format!("the tag of `{}` does not fit the tag type", variant.ident);
+        quote_spanned!(tag.span()=>
+            ::core::assert!(
+                <#tag_type as #krate::base::Discriminant>::MIN_DISCRIMINANT <= (#tag) as i128
+                    && (#tag) as i128 <= <#tag_type as #krate::base::Discriminant>::MAX_DISCRIMINANT,
+                #message,
+            );
+        )
+    });
+    let count = values.len();
+    quote!(
+        #[allow(clippy::unnecessary_cast)]
+        const _: () = {
+            #(#fits)*
+            let tags: [i128; #count] = [#((#values) as i128),*];
+            let mut i = 0;
+            while i < tags.len() {
+                let mut j = i + 1;
+                while j < tags.len() {
+                    ::core::assert!(tags[i] != tags[j], "two variants have the same tag");
+                    j += 1;
+                }
+                i += 1;
+            }
+        };
+    )
+}
+
+/// Running total of the field sizes and their padding as a block expression,
+/// starting from the `start` bytes in front of the first field
+fn sum_field_sizes(
+    krate: &Path,
+    infos: &[FieldInfo],
+    start: proc_macro2::TokenStream,
+    accessor: impl Fn(&FieldInfo) -> proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let mut size_in_bytes = quote!(
+        let __size = #start;
+    );
+    for info in infos {
+        let field = accessor(info);
+        let field_size = if let Some(slot) = &info.bit_slot {
+            bit_group_size(slot)
+        } else if info.attrs.tag_from.is_some() {
+            quote!(#krate::base::Tagged::untagged_byte_size(#field))
+        } else if info.attrs.present_if.is_some() {
+            // a conditional field has no presence byte, only the value when there is one
+            quote!(::core::option::Option::map_or(
+                ::core::option::Option::as_ref(#field),
+                0,
+                #krate::base::ByteSize::byte_size,
+            ))
+        } else if info.attrs.is_unprefixed_sequence() {
+            // the elements without a length prefix
+            quote!(::core::iter::Iterator::sum::<usize>(::core::iter::Iterator::map(
+                (#field).iter(),
+                #krate::base::ByteSize::byte_size,
+            )))
+        } else if let Some(len_type) = &info.attrs.len_type {
+            // the elements behind a prefix of the chosen type
+            quote!(
+                <#len_type as #krate::base::ConstByteSize>::const_byte_size()
+                    + ::core::iter::Iterator::sum::<usize>(::core::iter::Iterator::map(
+                        (#field).iter(),
+                        #krate::base::ByteSize::byte_size,
+                    ))
+            )
+        } else {
+            quote!(#krate::base::ByteSize::byte_size(#field))
+        };
+        size_in_bytes.extend(add_field_size(krate, info, field_size));
+    }
+    quote!({
+        #size_in_bytes
+        __size
+    })
+}
+
+/// The whole bitfield group counts towards its first field, the others take no bytes of their own
+fn bit_group_size(slot: &BitSlot) -> proc_macro2::TokenStream {
+    let group_bytes = slot.group_bytes.unwrap_or(0);
+    quote!(#group_bytes)
+}
 
-        //                 enum_variants_into_bson.extend(quote!(
-        //                     Self::#variant_name(#destructure_quote) => bson::Bson::Document(bson::doc!(
-        //                         stringify!(#variant_name): IntoBson::into_bson(vec![#bson_vec_quote]),
-        //                     )),
-        //                 ));
-        //             } // _ => panic!("Only implemented for unit or tuple kinded variants"),
-        //         }
-        //     }
-        //     let gen = quote! {
-        //         impl IntoBson for #name {
-        //             fn into_bson(self) -> bson::Bson {
-        //                 match self {
-        //                     #enum_variants_into_bson
-        //                 }
-        //             }
-        //         }
-        //     };
-        //     gen.into()
-        // }
-        syn::Data::Union(_) => unimplemented!(),
+/// Add `field_size` to the running `__size`, with the padding of the field's `align` and `pad_to`
+fn add_field_size(
+    krate: &Path,
+    info: &FieldInfo,
+    field_size: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let mut size_in_bytes = quote!();
+    if let Some(align) = info.attrs.align {
+        size_in_bytes.extend(quote!(
+            let __size = __size + #krate::base::align_padding(__size, #align);
+        ));
     }
-}
\ No newline at end of file
+    // content over `pad_to` is a `PaddingOverflow` for pack and unpack, which a size cannot report,
+    // so it counts in full and the buffer is large enough to reach that error
+    let field_size = match info.attrs.pad_to {
+        Some(pad_to) => quote!(::core::cmp::Ord::max(#field_size, #pad_to)),
+        None => field_size,
+    };
+    size_in_bytes.extend(quote!(
+        let __size = __size + #field_size;
+    ));
+    size_in_bytes
+}
+
+/// Bytes of the magic in front of the fields of a struct
+fn container_magic_size(container: &ContainerAttrs) -> proc_macro2::TokenStream {
+    let magic_len = container.magic.as_ref().map_or(0, |magic| magic.value().len());
+    quote!(#magic_len)
+}
+
+/// Grow the running `__size` by the trailing padding of a struct
+fn add_container_padding(krate: &Path, container: &ContainerAttrs) -> proc_macro2::TokenStream {
+    let mut padding_size = quote!();
+    if let Some(pad_to) = container.pad_to {
+        padding_size.extend(quote!(
+            let __size = ::core::cmp::Ord::max(__size, #pad_to);
+        ));
+    }
+    if let Some(align) = container.align {
+        padding_size.extend(quote!(
+            let __size = __size + #krate::base::align_padding(__size, #align);
+        ));
+    }
+    padding_size
+}
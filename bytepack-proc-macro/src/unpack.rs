@@ -1,93 +1,509 @@
 use proc_macro::TokenStream;
-use quote::{format_ident, quote};
-use syn::{parse_macro_input, DeriveInput};
+use quote::quote;
+use syn::{DeriveInput, Path};
+
+use crate::attr::{ContainerAttrs, LenFrom, Sequence};
+use crate::fields::{
+    align_origin, bind_fields, bit_group, check_drain_last, enum_tags, field_assertion,
+    field_condition, field_endian, field_infos, field_len_from, preceding_field, sequence_elem_type,
+    FieldInfo,
+};
+use crate::generics::bounded_generics;
+use crate::union_error;
 
 pub fn impl_byteunpack(ast: &DeriveInput) -> TokenStream {
+    match byteunpack(ast) {
+        Ok(gen) => gen.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+pub fn impl_byteunpack_endian(ast: &DeriveInput) -> TokenStream {
+    match byteunpack_endian(ast) {
+        Ok(gen) => gen.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn byteunpack(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
     let name = &ast.ident;
+    let container = ContainerAttrs::parse(&ast.attrs)?;
+    let krate = &container.crate_path();
+    let generics = bounded_generics(
+        &ast.generics,
+        quote!(#krate::unpack::ByteUnpack),
+        &container.bounds.unpack,
+    );
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
     match &ast.data {
         syn::Data::Struct(data) => {
-            let mut fields_byteunpack = quote!();
-            let mut new_self = quote!();
-            match &data.fields {
-                syn::Fields::Named(fields) => {
-                    for field in fields.named.iter() {
-                        let field_name = field.ident.as_ref().unwrap();
-                        fields_byteunpack.extend(quote!(
-                            let #field_name = ByteUnpack::unpack(buf)?;
-                            let byte_size = ByteSize::byte_size(&#field_name);
-                            let buf = &buf[byte_size..];
-                        ));
-                        new_self.extend(quote!(
-                            #field_name,
-                        ));
+            let infos = field_infos(&data.fields, &container)?;
+            let fields_byteunpack = unpack_fields(krate, &infos, false)?;
+            // the fields align relative to the start of the struct, in front of its magic
+            let start = align_origin(&infos);
+            let magic_byteunpack = unpack_container_magic(krate, &container);
+            let padding_byteunpack = unpack_container_padding(krate, &container);
+            let new_self = bind_fields(quote!(Self), &data.fields, &infos);
+            Ok(quote! {
+                impl #impl_generics #krate::unpack::ByteUnpack for #name #ty_generics #where_clause {
+                    fn unpack_from(
+                        __buf: &[u8],
+                    ) -> ::core::result::Result<(Self, usize), #krate::unpack::UnpackError> {
+                        let __offset = 0;
+                        #start
+                        #magic_byteunpack
+                        #fields_byteunpack
+                        #padding_byteunpack
+                        ::core::result::Result::Ok((#new_self, __offset))
                     }
                 }
-                syn::Fields::Unnamed(_) => unimplemented!(),
-                syn::Fields::Unit => {},
+            })
+        },
+        syn::Data::Enum(data) => {
+            let tags = enum_tags(ast, data)?;
+            let tag_type = &tags.tag_type;
+            let variants_byteunpack = unpack_variants(krate, &container, data, &tags.values, false)?;
+            Ok(quote! {
+                impl #impl_generics #krate::unpack::UntaggedByteUnpack for #name #ty_generics #where_clause {
+                    fn unpack_untagged(
+                        __tag: &Self::Tag,
+                        __buf: &[u8],
+                    ) -> ::core::result::Result<(Self, usize), #krate::unpack::UnpackError> {
+                        let __value = #krate::base::Discriminant::to_discriminant(__tag);
+                        let __offset = 0;
+                        #variants_byteunpack
+                        ::core::result::Result::Err(#krate::unpack::UnpackError::InvalidDiscriminant {
+                            offset: 0,
+                            value: __value,
+                        })
+                    }
+                }
+
+                impl #impl_generics #krate::unpack::ByteUnpack for #name #ty_generics #where_clause {
+                    fn unpack_from(
+                        __buf: &[u8],
+                    ) -> ::core::result::Result<(Self, usize), #krate::unpack::UnpackError> {
+                        let (__tag, __offset) =
+                            <#tag_type as #krate::unpack::ByteUnpack>::unpack_from(__buf)?;
+                        let __value = #krate::base::Discriminant::to_discriminant(&__tag);
+                        #variants_byteunpack
+                        ::core::result::Result::Err(#krate::unpack::UnpackError::InvalidDiscriminant {
+                            offset: 0,
+                            value: __value,
+                        })
+                    }
+                }
+            })
+        },
+        syn::Data::Union(_) => Err(union_error(ast)),
+    }
+}
+
+fn byteunpack_endian(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &ast.ident;
+    let container = ContainerAttrs::parse(&ast.attrs)?;
+    let krate = &container.crate_path();
+    let generics = bounded_generics(
+        &ast.generics,
+        quote!(#krate::unpack::ByteUnpackEndian),
+        &container.bounds.unpack,
+    );
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let mut untagged_impl = quote!();
+    let body = match &ast.data {
+        syn::Data::Struct(data) => {
+            let infos = field_infos(&data.fields, &container)?;
+            let fields_byteunpack = unpack_fields(krate, &infos, true)?;
+            // the fields align relative to the start of the struct, in front of its magic
+            let start = align_origin(&infos);
+            let magic_byteunpack = unpack_container_magic(krate, &container);
+            let padding_byteunpack = unpack_container_padding(krate, &container);
+            let new_self = bind_fields(quote!(Self), &data.fields, &infos);
+            quote! {
+                let __offset = 0;
+                #start
+                #magic_byteunpack
+                #fields_byteunpack
+                #padding_byteunpack
+                ::core::result::Result::Ok((#new_self, __offset))
             }
-            let gen = quote! {
-                impl ByteUnpack for #name {
-                    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-                        #fields_byteunpack
-                        Ok(Self {
-                            #new_self
+        },
+        syn::Data::Enum(data) => {
+            let tags = enum_tags(ast, data)?;
+            let tag_type = &tags.tag_type;
+            let variants_byteunpack = unpack_variants(krate, &container, data, &tags.values, true)?;
+            untagged_impl = quote! {
+                impl #impl_generics #krate::unpack::UntaggedByteUnpackEndian for #name #ty_generics #where_clause {
+                    fn unpack_untagged_endian(
+                        __tag: &Self::Tag,
+                        __buf: &[u8],
+                        __endian: #krate::base::Endian,
+                    ) -> ::core::result::Result<(Self, usize), #krate::unpack::UnpackError> {
+                        let __value = #krate::base::Discriminant::to_discriminant(__tag);
+                        let __offset = 0;
+                        #variants_byteunpack
+                        ::core::result::Result::Err(#krate::unpack::UnpackError::InvalidDiscriminant {
+                            offset: 0,
+                            value: __value,
                         })
                     }
                 }
             };
-            gen.into()
+            quote! {
+                let (__tag, __offset) =
+                    <#tag_type as #krate::unpack::ByteUnpackEndian>::unpack_endian_from(__buf, __endian)?;
+                let __value = #krate::base::Discriminant::to_discriminant(&__tag);
+                #variants_byteunpack
+                ::core::result::Result::Err(#krate::unpack::UnpackError::InvalidDiscriminant {
+                    offset: 0,
+                    value: __value,
+                })
+            }
         },
-        syn::Data::Enum(_) => unimplemented!(),
-        // syn::Data::Enum(data) => {
-        //     let mut enum_variants_into_bson = quote!();
-        //     for variant in &data.variants {
-        //         let variant_name = &variant.ident;
-        //         match variant.fields.len() {
-        //             0 => {
-        //                 enum_variants_into_bson.extend(quote!(
-        //                     Self::#variant_name => bson::Bson::String(stringify!(#variant_name).to_string()),
-        //                 ));
-        //             }
-        //             1 => {
-        //                 enum_variants_into_bson.extend(quote!(
-        //                     Self::#variant_name(val) => bson::Bson::Document(bson::doc!(
-        //                         stringify!(#variant_name): IntoBson::into_bson(val),
-        //                     )),
-        //                 ));
-        //             }
-        //             n => {
-        //                 let mut destructure_quote = quote!();
-        //                 let mut bson_vec_quote = quote!();
-        //                 destructure_quote.extend(
-        //                     (0..n)
-        //                         .map(|i| format_ident!("val_{i}"))
-        //                         .map(|val_i| quote!(#val_i,)),
-        //                 );
-        //                 bson_vec_quote.extend(
-        //                     (0..n)
-        //                         .map(|i| format_ident!("val_{i}"))
-        //                         .map(|val_i| quote!(IntoBson::into_bson(#val_i),)),
-        //                 );
-
-        //                 enum_variants_into_bson.extend(quote!(
-        //                     Self::#variant_name(#destructure_quote) => bson::Bson::Document(bson::doc!(
-        //                         stringify!(#variant_name): IntoBson::into_bson(vec![#bson_vec_quote]),
-        //                     )),
-        //                 ));
-        //             } // _ => panic!("Only implemented for unit or tuple kinded variants"),
-        //         }
-        //     }
-        //     let gen = quote! {
-        //         impl IntoBson for #name {
-        //             fn into_bson(self) -> bson::Bson {
-        //                 match self {
-        //                     #enum_variants_into_bson
-        //                 }
-        //             }
-        //         }
-        //     };
-        //     gen.into()
-        // }
-        syn::Data::Union(_) => unimplemented!(),
+        syn::Data::Union(_) => return Err(union_error(ast)),
+    };
+    Ok(quote! {
+        #untagged_impl
+
+        impl #impl_generics #krate::unpack::ByteUnpackEndian for #name #ty_generics #where_clause {
+            fn unpack_endian_from(
+                __buf: &[u8],
+                __endian: #krate::base::Endian,
+            ) -> ::core::result::Result<(Self, usize), #krate::unpack::UnpackError> {
+                #body
+            }
+        }
+    })
+}
+
+/// An `if` per variant comparing `__value` to its tag and unpacking its fields from `__offset`
+fn unpack_variants(
+    krate: &Path,
+    container: &ContainerAttrs,
+    data: &syn::DataEnum,
+    tags: &[syn::Expr],
+    in_context: bool,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let mut variants_byteunpack = quote!();
+    for (variant, tag) in data.variants.iter().zip(tags) {
+        let variant_name = &variant.ident;
+        let infos = field_infos(&variant.fields, container)?;
+        let start = align_origin(&infos);
+        let fields_byteunpack = unpack_fields(krate, &infos, in_context)?;
+        let new_variant = bind_fields(quote!(Self::#variant_name), &variant.fields, &infos);
+        variants_byteunpack.extend(quote! {
+            if __value == (#tag) as i128 {
+                #start
+                #fields_byteunpack
+                return ::core::result::Result::Ok((#new_variant, __offset));
+            }
+        });
+    }
+    Ok(variants_byteunpack)
+}
+
+/// Unpack each field in order into its binding, advancing `__offset` by the bytes consumed.
+///
+/// With `in_context` the fields follow the runtime `__endian`,
+/// which a `byte_order_mark` field also switches on for the fields after it.
+fn unpack_fields(
+    krate: &Path,
+    infos: &[FieldInfo],
+    mut in_context: bool,
+) -> syn::Result<proc_macro2::TokenStream> {
+    check_drain_last(infos)?;
+    let mut fields_byteunpack = quote!();
+    for (i, info) in infos.iter().enumerate() {
+        let binding = &info.binding;
+        let field_type = &info.field.ty;
+        if let Some(slot) = &info.bit_slot {
+            // the first field unpacks the whole group
+            if let Some(group_bytes) = slot.group_bytes {
+                let endian = field_endian(krate, info, in_context);
+                fields_byteunpack.extend(unpack_bit_group(krate, infos, i, endian, group_bytes));
+            }
+            continue;
+        }
+        if let Some(align) = info.attrs.align {
+            fields_byteunpack.extend(unpack_align(krate, quote!(__start), align));
+        }
+        if info.attrs.pad_to.is_some() {
+            fields_byteunpack.extend(quote!(
+                let __field_start = __offset;
+            ));
+        }
+        if let Some(member) = &info.attrs.tag_from {
+            let header = &preceding_field(infos, i, member)?.binding;
+            let unpack_untagged = match field_endian(krate, info, in_context) {
+                Some(endian) => quote!(
+                    <#field_type as #krate::unpack::UntaggedByteUnpackEndian>::unpack_untagged_endian(
+                        &__tag,
+                        #krate::unpack::unpack_rest(__buf, __offset)?,
+                        #endian,
+                    )
+                ),
+                None => quote!(
+                    <#field_type as #krate::unpack::UntaggedByteUnpack>::unpack_untagged(
+                        &__tag,
+                        #krate::unpack::unpack_rest(__buf, __offset)?,
+                    )
+                ),
+            };
+            fields_byteunpack.extend(quote!(
+                let __value = #krate::base::Discriminant::to_discriminant(&#header);
+                // a header value beyond the tag type names no variant, rather than a truncated one
+                let __tag = #krate::base::Discriminant::try_from_discriminant(__value).ok_or(
+                    #krate::unpack::UnpackError::InvalidDiscriminant { offset: __offset, value: __value },
+                )?;
+                let (#binding, __size) = #unpack_untagged.map_err(|e| e.shifted(__offset))?;
+            ));
+        } else if let Some(condition) = field_condition(infos, i, |info| {
+            let binding = &info.binding;
+            quote!(&#binding)
+        }) {
+            // the value type is inferred from the `Option` field it is wrapped into
+            let unpack_field = unpack_value(krate, quote!(_), field_endian(krate, info, in_context));
+            fields_byteunpack.extend(quote!(
+                let (#binding, __size) = if #condition {
+                    let (__value, __size) = #unpack_field;
+                    (::core::option::Option::Some(__value), __size)
+                } else {
+                    (::core::option::Option::None, 0)
+                };
+            ));
+        } else if let Some(len_from) = &info.attrs.len_from {
+            let elem_type = sequence_elem_type(field_type)?;
+            let len = field_len_from(krate, infos, i, |info| {
+                let binding = &info.binding;
+                quote!(&#binding)
+            });
+            let endian = field_endian(krate, info, in_context);
+            let unpack_sequence = match (len_from, endian) {
+                (LenFrom::Count(_), Some(endian)) => quote!(
+                    #krate::unpack::unpack_count_endian::<#elem_type>(
+                        #krate::unpack::unpack_rest(__buf, __offset)?,
+                        __len,
+                        #endian,
+                    )
+                ),
+                (LenFrom::Count(_), None) => quote!(
+                    #krate::unpack::unpack_count::<#elem_type>(
+                        #krate::unpack::unpack_rest(__buf, __offset)?,
+                        __len,
+                    )
+                ),
+                (LenFrom::Bytes(_), Some(endian)) => quote!(
+                    #krate::unpack::unpack_drain_endian::<#elem_type>(
+                        #krate::unpack::unpack_bytes(__buf, __offset, __len)?,
+                        #endian,
+                    )
+                ),
+                (LenFrom::Bytes(_), None) => quote!(
+                    #krate::unpack::unpack_drain::<#elem_type>(
+                        #krate::unpack::unpack_bytes(__buf, __offset, __len)?,
+                    )
+                ),
+            };
+            fields_byteunpack.extend(quote!(
+                let __len = #len.ok_or(#krate::unpack::UnpackError::LengthOverflow { offset: __offset })?;
+                let (__value, __size) = #unpack_sequence.map_err(|e| e.shifted(__offset))?;
+                let #binding: #field_type = ::core::convert::From::from(__value);
+            ));
+        } else if info.attrs.sequence == Some(Sequence::Splat) {
+            return Err(syn::Error::new_spanned(
+                info.field,
+                "a splat field has no length on the wire and cannot be unpacked, use `drain`",
+            ));
+        } else if info.attrs.sequence == Some(Sequence::Drain) {
+            let elem_type = sequence_elem_type(field_type)?;
+            let unpack_drain = match field_endian(krate, info, in_context) {
+                Some(endian) => quote!(
+                    #krate::unpack::unpack_drain_endian::<#elem_type>(
+                        #krate::unpack::unpack_rest(__buf, __offset)?,
+                        #endian,
+                    )
+                ),
+                None => quote!(
+                    #krate::unpack::unpack_drain::<#elem_type>(
+                        #krate::unpack::unpack_rest(__buf, __offset)?,
+                    )
+                ),
+            };
+            fields_byteunpack.extend(quote!(
+                let (__value, __size) = #unpack_drain.map_err(|e| e.shifted(__offset))?;
+                let #binding: #field_type = ::core::convert::From::from(__value);
+            ));
+        } else if let Some(len_type) = &info.attrs.len_type {
+            let elem_type = sequence_elem_type(field_type)?;
+            let unpack_sized = match field_endian(krate, info, in_context) {
+                Some(endian) => quote!(
+                    #krate::unpack::unpack_sized_endian::<#elem_type, #len_type>(
+                        #krate::unpack::unpack_rest(__buf, __offset)?,
+                        #endian,
+                    )
+                ),
+                None => quote!(
+                    #krate::unpack::unpack_sized::<#elem_type, #len_type>(
+                        #krate::unpack::unpack_rest(__buf, __offset)?,
+                    )
+                ),
+            };
+            fields_byteunpack.extend(quote!(
+                let (__value, __size) = #unpack_sized.map_err(|e| e.shifted(__offset))?;
+                let #binding: #field_type = ::core::convert::From::from(__value);
+            ));
+        } else {
+            let unpack_field = unpack_value(krate, quote!(#field_type), field_endian(krate, info, in_context));
+            fields_byteunpack.extend(quote!(
+                let (#binding, __size) = #unpack_field;
+            ));
+        }
+        fields_byteunpack.extend(unpack_assertion(krate, infos, i));
+        if info.attrs.byte_order_mark {
+            fields_byteunpack.extend(quote!(
+                let __endian = #krate::base::ByteOrderMark::endian(&#binding).ok_or(
+                    #krate::unpack::UnpackError::UnknownByteOrder { offset: __offset },
+                )?;
+            ));
+            in_context = true;
+        }
+        fields_byteunpack.extend(quote!(
+            let __offset = __offset + __size;
+        ));
+        if let Some(pad_to) = info.attrs.pad_to {
+            fields_byteunpack.extend(unpack_pad_to(krate, quote!(__field_start), pad_to));
+        }
+    }
+    Ok(fields_byteunpack)
+}
+
+/// Unpack the `group_bytes` bytes of the bitfield group at `index` and split them into its fields
+fn unpack_bit_group(
+    krate: &Path,
+    infos: &[FieldInfo],
+    index: usize,
+    endian: Option<proc_macro2::TokenStream>,
+    group_bytes: usize,
+) -> proc_macro2::TokenStream {
+    let group = bit_group(infos, index);
+    let endian = endian.unwrap_or_else(|| quote!(#krate::base::Endian::Big));
+    let mut group_byteunpack = quote!(
+        let (__bits, __size) = #krate::unpack::unpack_bits(
+            #krate::unpack::unpack_rest(__buf, __offset)?,
+            #group_bytes,
+            #endian,
+        )
+        .map_err(|e| e.shifted(__offset))?;
+    );
+    for info in group {
+        let binding = &info.binding;
+        let field_type = &info.field.ty;
+        let Some(slot) = &info.bit_slot else {
+            unreachable!("every field of a bitfield group has a slot");
+        };
+        let (width, shift) = (slot.width, slot.shift);
+        group_byteunpack.extend(quote!(
+            let #binding: #field_type = #krate::unpack::unpack_bitfield(__bits >> #shift, #width)
+                .map_err(|e| e.shifted(__offset))?;
+        ));
+    }
+    for i in index..index + group.len() {
+        group_byteunpack.extend(unpack_assertion(krate, infos, i));
+    }
+    group_byteunpack.extend(quote!(
+        let __offset = __offset + __size;
+    ));
+    group_byteunpack
+}
+
+/// Fail with `AssertionFailed` unless the field at `index` meets its `assert`
+fn unpack_assertion(krate: &Path, infos: &[FieldInfo], index: usize) -> proc_macro2::TokenStream {
+    let Some((check, field, assertion)) = field_assertion(infos, index, |info| {
+        let binding = &info.binding;
+        quote!(&#binding)
+    }) else {
+        return quote!();
+    };
+    quote!(
+        if !#check {
+            return ::core::result::Result::Err(#krate::unpack::UnpackError::AssertionFailed {
+                offset: __offset,
+                field: #field,
+                assertion: #assertion,
+            });
+        }
+    )
+}
+
+/// Skip the magic bytes in front of the fields of a struct, failing if the input holds others
+fn unpack_container_magic(krate: &Path, container: &ContainerAttrs) -> proc_macro2::TokenStream {
+    let Some(magic) = &container.magic else {
+        return quote!();
+    };
+    quote!(
+        let __size = #krate::unpack::unpack_magic(#krate::unpack::unpack_rest(__buf, __offset)?, #magic)
+            .map_err(|e| e.shifted(__offset))?;
+        let __offset = __offset + __size;
+    )
+}
+
+/// Skip the trailing padding of a struct, first up to `pad_to` and then to a multiple of `align`
+fn unpack_container_padding(krate: &Path, container: &ContainerAttrs) -> proc_macro2::TokenStream {
+    let mut padding_byteunpack = quote!();
+    if let Some(pad_to) = container.pad_to {
+        padding_byteunpack.extend(unpack_pad_to(krate, quote!(0), pad_to));
+    }
+    if let Some(align) = container.align {
+        padding_byteunpack.extend(unpack_align(krate, quote!(0), align));
+    }
+    padding_byteunpack
+}
+
+/// Skip padding at `__offset` so the bytes since `start` become a multiple of `align`
+fn unpack_align(krate: &Path, start: proc_macro2::TokenStream, align: usize) -> proc_macro2::TokenStream {
+    quote!(
+        let __size = #krate::unpack::unpack_padding(
+            #krate::unpack::unpack_rest(__buf, __offset)?,
+            #krate::base::align_padding(__offset - #start, #align),
+        )
+        .map_err(|e| e.shifted(__offset))?;
+        let __offset = __offset + __size;
+    )
+}
+
+/// Skip padding at `__offset` so the bytes since `start` add up to `pad_to`
+fn unpack_pad_to(krate: &Path, start: proc_macro2::TokenStream, pad_to: usize) -> proc_macro2::TokenStream {
+    quote!(
+        let __size = #krate::unpack::unpack_pad_to(
+            #krate::unpack::unpack_rest(__buf, __offset)?,
+            __offset - #start,
+            #pad_to,
+        )
+        .map_err(|e| e.shifted(__offset))?;
+        let __offset = __offset + __size;
+    )
+}
+
+/// Unpack a `value_type` at `__offset`, in `endian` if given,
+/// evaluating to the value and the bytes consumed
+fn unpack_value(
+    krate: &Path,
+    value_type: proc_macro2::TokenStream,
+    endian: Option<proc_macro2::TokenStream>,
+) -> proc_macro2::TokenStream {
+    match endian {
+        Some(endian) => quote!(
+            <#value_type as #krate::unpack::ByteUnpackEndian>::unpack_endian_from(
+                #krate::unpack::unpack_rest(__buf, __offset)?,
+                #endian,
+            )
+            .map_err(|e| e.shifted(__offset))?
+        ),
+        None => quote!(
+            <#value_type as #krate::unpack::ByteUnpack>::unpack_from(
+                #krate::unpack::unpack_rest(__buf, __offset)?,
+            )
+            .map_err(|e| e.shifted(__offset))?
+        ),
     }
-}
\ No newline at end of file
+}
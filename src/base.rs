@@ -1,4 +1,4 @@
-use std::{cell::RefCell, ops::{Deref, DerefMut}, rc::Rc, marker::PhantomData, sync::Arc};
+use std::{cell::RefCell, collections::VecDeque, ops::{self, Deref, DerefMut}, rc::Rc, marker::PhantomData, sync::Arc};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct LEu16(pub u16);
@@ -24,13 +24,253 @@ pub struct LEi64(pub i64);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct LEi128(pub i128);
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct LEf32(pub f32);
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct LEf64(pub f64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BEu16(pub u16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BEu32(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BEu64(pub u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BEu128(pub u128);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BEi16(pub i16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BEi32(pub i32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BEi64(pub i64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BEi128(pub i128);
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct BEf32(pub f32);
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct BEf64(pub f64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NEu16(pub u16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NEu32(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NEu64(pub u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NEu128(pub u128);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NEi16(pub i16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NEi32(pub i32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NEi64(pub i64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NEi128(pub i128);
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct NEf32(pub f32);
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct NEf64(pub f64);
+
+// Conversions and arithmetic so the wrappers can be used like the integers they hold
+macro_rules! imp_wrapped_num {
+    ($wrapped_type: ident, $num_type: ty) => {
+        impl From<$num_type> for $wrapped_type {
+            fn from(val: $num_type) -> Self {
+                Self(val)
+            }
+        }
+        impl From<$wrapped_type> for $num_type {
+            fn from(val: $wrapped_type) -> Self {
+                val.0
+            }
+        }
+        imp_wrapped_num_op!($wrapped_type, $num_type, Add, add, AddAssign, add_assign);
+        imp_wrapped_num_op!($wrapped_type, $num_type, Sub, sub, SubAssign, sub_assign);
+        imp_wrapped_num_op!($wrapped_type, $num_type, Mul, mul, MulAssign, mul_assign);
+        imp_wrapped_num_op!($wrapped_type, $num_type, Div, div, DivAssign, div_assign);
+        imp_wrapped_num_op!($wrapped_type, $num_type, Rem, rem, RemAssign, rem_assign);
+        imp_wrapped_num_op!($wrapped_type, $num_type, BitAnd, bitand, BitAndAssign, bitand_assign);
+        imp_wrapped_num_op!($wrapped_type, $num_type, BitOr, bitor, BitOrAssign, bitor_assign);
+        imp_wrapped_num_op!($wrapped_type, $num_type, BitXor, bitxor, BitXorAssign, bitxor_assign);
+        impl ops::Shl<u32> for $wrapped_type {
+            type Output = Self;
+            fn shl(self, rhs: u32) -> Self {
+                Self(self.0 << rhs)
+            }
+        }
+        impl ops::ShlAssign<u32> for $wrapped_type {
+            fn shl_assign(&mut self, rhs: u32) {
+                self.0 <<= rhs;
+            }
+        }
+        impl ops::Shr<u32> for $wrapped_type {
+            type Output = Self;
+            fn shr(self, rhs: u32) -> Self {
+                Self(self.0 >> rhs)
+            }
+        }
+        impl ops::ShrAssign<u32> for $wrapped_type {
+            fn shr_assign(&mut self, rhs: u32) {
+                self.0 >>= rhs;
+            }
+        }
+        impl ops::Not for $wrapped_type {
+            type Output = Self;
+            fn not(self) -> Self {
+                Self(!self.0)
+            }
+        }
+    };
+}
+
+// `wrapped op wrapped` and `wrapped op native`, both also as assignment
+macro_rules! imp_wrapped_num_op {
+    ($wrapped_type: ident, $num_type: ty, $op: ident, $op_fn: ident, $op_assign: ident, $op_assign_fn: ident) => {
+        impl ops::$op for $wrapped_type {
+            type Output = Self;
+            fn $op_fn(self, rhs: Self) -> Self {
+                Self(ops::$op::$op_fn(self.0, rhs.0))
+            }
+        }
+        impl ops::$op<$num_type> for $wrapped_type {
+            type Output = Self;
+            fn $op_fn(self, rhs: $num_type) -> Self {
+                Self(ops::$op::$op_fn(self.0, rhs))
+            }
+        }
+        impl ops::$op_assign for $wrapped_type {
+            fn $op_assign_fn(&mut self, rhs: Self) {
+                ops::$op_assign::$op_assign_fn(&mut self.0, rhs.0);
+            }
+        }
+        impl ops::$op_assign<$num_type> for $wrapped_type {
+            fn $op_assign_fn(&mut self, rhs: $num_type) {
+                ops::$op_assign::$op_assign_fn(&mut self.0, rhs);
+            }
+        }
+    };
+}
+
+macro_rules! imp_wrapped_float {
+    ($wrapped_type: ident, $num_type: ty) => {
+        impl From<$num_type> for $wrapped_type {
+            fn from(val: $num_type) -> Self {
+                Self(val)
+            }
+        }
+        impl From<$wrapped_type> for $num_type {
+            fn from(val: $wrapped_type) -> Self {
+                val.0
+            }
+        }
+        imp_wrapped_num_op!($wrapped_type, $num_type, Add, add, AddAssign, add_assign);
+        imp_wrapped_num_op!($wrapped_type, $num_type, Sub, sub, SubAssign, sub_assign);
+        imp_wrapped_num_op!($wrapped_type, $num_type, Mul, mul, MulAssign, mul_assign);
+        imp_wrapped_num_op!($wrapped_type, $num_type, Div, div, DivAssign, div_assign);
+        imp_wrapped_num_op!($wrapped_type, $num_type, Rem, rem, RemAssign, rem_assign);
+        impl ops::Neg for $wrapped_type {
+            type Output = Self;
+            fn neg(self) -> Self {
+                Self(-self.0)
+            }
+        }
+    };
+}
+
+macro_rules! imp_wrapped_signed_num {
+    ($wrapped_type: ident, $num_type: ty) => {
+        imp_wrapped_num!($wrapped_type, $num_type);
+        impl ops::Neg for $wrapped_type {
+            type Output = Self;
+            fn neg(self) -> Self {
+                Self(-self.0)
+            }
+        }
+    };
+}
+
+imp_wrapped_num!(LEu16, u16);
+imp_wrapped_num!(LEu32, u32);
+imp_wrapped_num!(LEu64, u64);
+imp_wrapped_num!(LEu128, u128);
+imp_wrapped_signed_num!(LEi16, i16);
+imp_wrapped_signed_num!(LEi32, i32);
+imp_wrapped_signed_num!(LEi64, i64);
+imp_wrapped_signed_num!(LEi128, i128);
+imp_wrapped_float!(LEf32, f32);
+imp_wrapped_float!(LEf64, f64);
+imp_wrapped_num!(BEu16, u16);
+imp_wrapped_num!(BEu32, u32);
+imp_wrapped_num!(BEu64, u64);
+imp_wrapped_num!(BEu128, u128);
+imp_wrapped_signed_num!(BEi16, i16);
+imp_wrapped_signed_num!(BEi32, i32);
+imp_wrapped_signed_num!(BEi64, i64);
+imp_wrapped_signed_num!(BEi128, i128);
+imp_wrapped_float!(BEf32, f32);
+imp_wrapped_float!(BEf64, f64);
+imp_wrapped_num!(NEu16, u16);
+imp_wrapped_num!(NEu32, u32);
+imp_wrapped_num!(NEu64, u64);
+imp_wrapped_num!(NEu128, u128);
+imp_wrapped_signed_num!(NEi16, i16);
+imp_wrapped_signed_num!(NEi32, i32);
+imp_wrapped_signed_num!(NEi64, i64);
+imp_wrapped_signed_num!(NEi128, i128);
+imp_wrapped_float!(NEf32, f32);
+imp_wrapped_float!(NEf64, f64);
+
+/// A `bool` that unpacks any non-zero byte as `true` instead of rejecting it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct LenientBool(pub bool);
+
+impl From<bool> for LenientBool {
+    fn from(val: bool) -> Self {
+        Self(val)
+    }
+}
+
+impl From<LenientBool> for bool {
+    fn from(val: LenientBool) -> Self {
+        val.0
+    }
+}
+
+/// Byte order of multi-byte numbers, chosen at the use site instead of by the type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endian {
+    /// Network byte order, the default for bare integers
+    #[default]
+    Big,
+    Little,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SplatVec<T>(pub Vec<T>);
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DrainVec<T>(pub Vec<T>);
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SplatDrain<T> {
     Splat(Vec<T>),
     Drain(Vec<T>),
@@ -54,18 +294,304 @@ impl<T> SplatDrain<T> {
     }
 }
 
+/// Length prefix of `Vec`, `String` and the other plain sequences
 pub type SizeType = u32;
 
-#[derive(Debug, Clone)]
-pub struct SizedVec<T>(pub Vec<T>);
+/// A `Vec` behind a length prefix of type `L`, built with `new` or `From<Vec<T>>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizedVec<T, L = SizeType>(pub Vec<T>, PhantomData<L>);
+
+impl<T, L> SizedVec<T, L> {
+    pub fn new(vec: Vec<T>) -> Self {
+        Self(vec, PhantomData)
+    }
+}
+
+impl<T, L> From<Vec<T>> for SizedVec<T, L> {
+    fn from(vec: Vec<T>) -> Self {
+        Self::new(vec)
+    }
+}
+
+/// A `String` behind a length prefix of type `L`, built with `new` or `From<String>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizedString<L = SizeType>(pub String, PhantomData<L>);
+
+impl<L> SizedString<L> {
+    pub fn new(string: String) -> Self {
+        Self(string, PhantomData)
+    }
+}
+
+impl<L> From<String> for SizedString<L> {
+    fn from(string: String) -> Self {
+        Self::new(string)
+    }
+}
+
+impl<L> From<&str> for SizedString<L> {
+    fn from(string: &str) -> Self {
+        Self::new(string.to_owned())
+    }
+}
+
+/// Unsigned integer types that can prefix the length of a sequence on the wire
+pub trait LengthPrefix: ConstByteSize + Sized {
+    /// The prefix for `len`, `None` if it does not fit
+    fn from_len(len: usize) -> Option<Self>;
+    /// The length the prefix holds, `None` if it does not fit into a `usize`
+    fn to_len(&self) -> Option<usize>;
+}
+
+macro_rules! imp_length_prefix {
+    ($num_type: ty) => {
+        impl LengthPrefix for $num_type {
+            fn from_len(len: usize) -> Option<Self> {
+                Self::try_from(len).ok()
+            }
+            fn to_len(&self) -> Option<usize> {
+                usize::try_from(*self).ok()
+            }
+        }
+    };
+}
+
+macro_rules! imp_length_prefix_for_wrapped_num {
+    ($wrapped_type: ident, $num_type: ty) => {
+        impl LengthPrefix for $wrapped_type {
+            fn from_len(len: usize) -> Option<Self> {
+                <$num_type>::try_from(len).ok().map(Self)
+            }
+            fn to_len(&self) -> Option<usize> {
+                usize::try_from(self.0).ok()
+            }
+        }
+    };
+}
+
+imp_length_prefix!(u8);
+imp_length_prefix!(u16);
+imp_length_prefix!(u32);
+imp_length_prefix!(u64);
+imp_length_prefix!(u128);
+imp_length_prefix_for_wrapped_num!(LEu16, u16);
+imp_length_prefix_for_wrapped_num!(LEu32, u32);
+imp_length_prefix_for_wrapped_num!(LEu64, u64);
+imp_length_prefix_for_wrapped_num!(LEu128, u128);
+imp_length_prefix_for_wrapped_num!(BEu16, u16);
+imp_length_prefix_for_wrapped_num!(BEu32, u32);
+imp_length_prefix_for_wrapped_num!(BEu64, u64);
+imp_length_prefix_for_wrapped_num!(BEu128, u128);
+imp_length_prefix_for_wrapped_num!(NEu16, u16);
+imp_length_prefix_for_wrapped_num!(NEu32, u32);
+imp_length_prefix_for_wrapped_num!(NEu64, u64);
+imp_length_prefix_for_wrapped_num!(NEu128, u128);
+
+/// Bytes of padding after `size` bytes to reach the next multiple of `align`
+pub fn align_padding(size: usize, align: usize) -> usize {
+    (align - size % align) % align
+}
+
+/// `N` values of `T` worth of padding, packed as `FILL` bytes and ignored on unpack
+#[derive(Debug)]
+pub struct Throw<T, const N: usize, const FILL: u8 = 0>(PhantomData<fn() -> T>);
+impl<T, const N: usize, const FILL: u8> Throw<T, N, FILL> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+impl<T, const N: usize, const FILL: u8> Default for Throw<T, N, FILL> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T, const N: usize, const FILL: u8> PartialEq for Throw<T, N, FILL> {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+impl<T, const N: usize, const FILL: u8> Eq for Throw<T, N, FILL> {}
 
+/// Reserved bytes like `Throw`, but unpacking rejects any byte other than `FILL`
 #[derive(Debug)]
-pub struct Throw<T, const N: usize>(PhantomData<fn() -> T>);
-impl<T, const N: usize> Throw<T, N> {
+pub struct Reserved<T, const N: usize, const FILL: u8 = 0>(PhantomData<fn() -> T>);
+impl<T, const N: usize, const FILL: u8> Reserved<T, N, FILL> {
     pub fn new() -> Self {
         Self(PhantomData)
     }
 }
+impl<T, const N: usize, const FILL: u8> Default for Reserved<T, N, FILL> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T, const N: usize, const FILL: u8> PartialEq for Reserved<T, N, FILL> {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+impl<T, const N: usize, const FILL: u8> Eq for Reserved<T, N, FILL> {}
+
+/// Integer types that can carry an enum tag on the wire
+pub trait Discriminant: Sized {
+    /// Smallest tag the type can hold
+    const MIN_DISCRIMINANT: i128;
+    /// Largest tag the type can hold, capped at `i128::MAX`
+    const MAX_DISCRIMINANT: i128;
+
+    fn from_discriminant(value: i128) -> Self;
+    fn to_discriminant(&self) -> i128;
+
+    /// Like `from_discriminant`, but `None` instead of truncating a value the type cannot hold
+    fn try_from_discriminant(value: i128) -> Option<Self> {
+        (Self::MIN_DISCRIMINANT..=Self::MAX_DISCRIMINANT)
+            .contains(&value)
+            .then(|| Self::from_discriminant(value))
+    }
+}
+
+macro_rules! imp_discriminant {
+    ($num_type: ty) => {
+        impl Discriminant for $num_type {
+            const MIN_DISCRIMINANT: i128 = <$num_type>::MIN as i128;
+            const MAX_DISCRIMINANT: i128 = if <$num_type>::MAX as u128 > i128::MAX as u128 {
+                i128::MAX
+            } else {
+                <$num_type>::MAX as i128
+            };
+
+            fn from_discriminant(value: i128) -> Self {
+                value as Self
+            }
+            fn to_discriminant(&self) -> i128 {
+                *self as i128
+            }
+        }
+    };
+}
+
+macro_rules! imp_discriminant_for_wrapped_num {
+    ($wrapped_type: ident, $num_type: ty) => {
+        impl Discriminant for $wrapped_type {
+            const MIN_DISCRIMINANT: i128 = <$num_type as Discriminant>::MIN_DISCRIMINANT;
+            const MAX_DISCRIMINANT: i128 = <$num_type as Discriminant>::MAX_DISCRIMINANT;
+
+            fn from_discriminant(value: i128) -> Self {
+                Self(value as $num_type)
+            }
+            fn to_discriminant(&self) -> i128 {
+                self.0 as i128
+            }
+        }
+    };
+}
+
+imp_discriminant!(u8);
+imp_discriminant!(u16);
+imp_discriminant!(u32);
+imp_discriminant!(u64);
+imp_discriminant!(u128);
+imp_discriminant!(i8);
+imp_discriminant!(i16);
+imp_discriminant!(i32);
+imp_discriminant!(i64);
+imp_discriminant!(i128);
+imp_discriminant_for_wrapped_num!(LEu16, u16);
+imp_discriminant_for_wrapped_num!(LEu32, u32);
+imp_discriminant_for_wrapped_num!(LEu64, u64);
+imp_discriminant_for_wrapped_num!(LEu128, u128);
+imp_discriminant_for_wrapped_num!(LEi16, i16);
+imp_discriminant_for_wrapped_num!(LEi32, i32);
+imp_discriminant_for_wrapped_num!(LEi64, i64);
+imp_discriminant_for_wrapped_num!(LEi128, i128);
+imp_discriminant_for_wrapped_num!(BEu16, u16);
+imp_discriminant_for_wrapped_num!(BEu32, u32);
+imp_discriminant_for_wrapped_num!(BEu64, u64);
+imp_discriminant_for_wrapped_num!(BEu128, u128);
+imp_discriminant_for_wrapped_num!(BEi16, i16);
+imp_discriminant_for_wrapped_num!(BEi32, i32);
+imp_discriminant_for_wrapped_num!(BEi64, i64);
+imp_discriminant_for_wrapped_num!(BEi128, i128);
+imp_discriminant_for_wrapped_num!(NEu16, u16);
+imp_discriminant_for_wrapped_num!(NEu32, u32);
+imp_discriminant_for_wrapped_num!(NEu64, u64);
+imp_discriminant_for_wrapped_num!(NEu128, u128);
+imp_discriminant_for_wrapped_num!(NEi16, i16);
+imp_discriminant_for_wrapped_num!(NEi32, i32);
+imp_discriminant_for_wrapped_num!(NEi64, i64);
+imp_discriminant_for_wrapped_num!(NEi128, i128);
+
+/// A magic value that announces the byte order of the data following it,
+/// e.g. the `II`/`MM` mark at the start of a TIFF file
+pub trait ByteOrderMark {
+    /// The announced byte order, or `None` if the value is not a valid mark
+    fn endian(&self) -> Option<Endian>;
+}
+
+impl ByteOrderMark for [u8; 2] {
+    fn endian(&self) -> Option<Endian> {
+        match self {
+            b"MM" => Some(Endian::Big),
+            b"II" => Some(Endian::Little),
+            _ => None,
+        }
+    }
+}
+
+/// Values that fit into the few bits of a `#[bytepack(bits = N)]` field.
+///
+/// `#[derive(BitField)]` implements it for enums whose variants have no fields, stored as their tag.
+pub trait BitField: Sized {
+    /// The value as raw bits, counted from the lowest bit
+    fn to_bits(&self) -> u128;
+    /// The value of raw bits, or `None` if they name no value
+    fn from_bits(bits: u128) -> Option<Self>;
+}
+
+impl BitField for bool {
+    fn to_bits(&self) -> u128 {
+        *self as u128
+    }
+
+    fn from_bits(bits: u128) -> Option<Self> {
+        match bits {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! imp_bitfield {
+    ($num_type: ty) => {
+        impl BitField for $num_type {
+            fn to_bits(&self) -> u128 {
+                *self as u128
+            }
+
+            fn from_bits(bits: u128) -> Option<Self> {
+                Self::try_from(bits).ok()
+            }
+        }
+    };
+}
+
+imp_bitfield!(u8);
+imp_bitfield!(u16);
+imp_bitfield!(u32);
+imp_bitfield!(u64);
+imp_bitfield!(u128);
+
+/// Enums whose tag can be stored apart from the variant fields,
+/// e.g. in a header field that precedes the enum
+pub trait Tagged {
+    type Tag: Discriminant;
+
+    fn tag(&self) -> Self::Tag;
+
+    /// Byte size of the variant fields without the tag
+    fn untagged_byte_size(&self) -> usize;
+}
 
 pub trait ConstByteSize {
     fn const_byte_size() -> usize;
@@ -96,18 +622,45 @@ imp_const_bytesize!(u32, 4);
 imp_const_bytesize!(u64, 8);
 imp_const_bytesize!(u128, 16);
 imp_const_bytesize!(LEu16, 2);
+imp_const_bytesize!(BEu16, 2);
+imp_const_bytesize!(NEu16, 2);
 imp_const_bytesize!(LEu32, 4);
+imp_const_bytesize!(BEu32, 4);
+imp_const_bytesize!(NEu32, 4);
 imp_const_bytesize!(LEu64, 8);
+imp_const_bytesize!(BEu64, 8);
+imp_const_bytesize!(NEu64, 8);
 imp_const_bytesize!(LEu128, 16);
+imp_const_bytesize!(BEu128, 16);
+imp_const_bytesize!(NEu128, 16);
 imp_const_bytesize!(i8, 1);
 imp_const_bytesize!(i16, 2);
 imp_const_bytesize!(i32, 4);
 imp_const_bytesize!(i64, 8);
 imp_const_bytesize!(i128, 16);
 imp_const_bytesize!(LEi16, 2);
+imp_const_bytesize!(BEi16, 2);
+imp_const_bytesize!(NEi16, 2);
 imp_const_bytesize!(LEi32, 4);
+imp_const_bytesize!(BEi32, 4);
+imp_const_bytesize!(NEi32, 4);
 imp_const_bytesize!(LEi64, 8);
+imp_const_bytesize!(BEi64, 8);
+imp_const_bytesize!(NEi64, 8);
 imp_const_bytesize!(LEi128, 16);
+imp_const_bytesize!(BEi128, 16);
+imp_const_bytesize!(NEi128, 16);
+imp_const_bytesize!(bool, 1);
+imp_const_bytesize!(LenientBool, 1);
+imp_const_bytesize!(char, 4);
+imp_const_bytesize!(f32, 4);
+imp_const_bytesize!(f64, 8);
+imp_const_bytesize!(LEf32, 4);
+imp_const_bytesize!(BEf32, 4);
+imp_const_bytesize!(NEf32, 4);
+imp_const_bytesize!(LEf64, 8);
+imp_const_bytesize!(BEf64, 8);
+imp_const_bytesize!(NEf64, 8);
 
 impl<T: ConstByteSize, const N: usize> ConstByteSize for [T; N] {
     fn const_byte_size() -> usize {
@@ -121,7 +674,13 @@ impl<T: ByteSize, const N: usize> ByteSize for [T; N] {
     }
 }
 
-impl<T: ByteSize> ByteSize for Box<T> {
+impl<T: ByteSize + ?Sized> ByteSize for &T {
+    fn byte_size(&self) -> usize {
+        (**self).byte_size()
+    }
+}
+
+impl<T: ByteSize + ?Sized> ByteSize for Box<T> {
     fn byte_size(&self) -> usize {
         self.deref().byte_size()
     }
@@ -145,18 +704,51 @@ impl<T: ByteSize> ByteSize for Arc<T> {
     }
 }
 
+macro_rules! imp_bytesize_for_tuple {
+    ($($elem_type: ident $index: tt),+) => {
+        impl<$($elem_type: ConstByteSize),+> ConstByteSize for ($($elem_type,)+) {
+            fn const_byte_size() -> usize {
+                0 $(+ $elem_type::const_byte_size())+
+            }
+        }
+        impl<$($elem_type: ByteSize),+> ByteSize for ($($elem_type,)+) {
+            fn byte_size(&self) -> usize {
+                0 $(+ self.$index.byte_size())+
+            }
+        }
+    };
+}
+
+imp_bytesize_for_tuple!(A 0);
+imp_bytesize_for_tuple!(A 0, B 1);
+imp_bytesize_for_tuple!(A 0, B 1, C 2);
+imp_bytesize_for_tuple!(A 0, B 1, C 2, D 3);
+imp_bytesize_for_tuple!(A 0, B 1, C 2, D 3, E 4);
+imp_bytesize_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+imp_bytesize_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+imp_bytesize_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+imp_bytesize_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8);
+imp_bytesize_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9);
+imp_bytesize_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10);
+imp_bytesize_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10, L 11);
+
+// One presence byte, followed by the value if there is one
+impl<T: ByteSize> ByteSize for Option<T> {
+    fn byte_size(&self) -> usize {
+        1 + self.as_ref().map_or(0, T::byte_size)
+    }
+}
+
 impl<T: ByteSize> ByteSize for SplatVec<T> {
     fn byte_size(&self) -> usize {
         self.0.iter().map(ByteSize::byte_size).sum()
     }
 }
 
-// TODO: what to do with context dependency
-//  - probably should only allow at the end
-//    then byte_size does not matter
+// The derives only allow a drain as the last field, so it spans exactly the elements it holds
 impl<T: ByteSize> ByteSize for DrainVec<T> {
     fn byte_size(&self) -> usize {
-        0
+        self.0.iter().map(ByteSize::byte_size).sum()
     }
 }
 
@@ -164,23 +756,52 @@ impl<T: ByteSize> ByteSize for SplatDrain<T> {
     fn byte_size(&self) -> usize {
         match self {
             Self::Splat(v) => v.iter().map(ByteSize::byte_size).sum(),
-            Self::Drain(_v) => 0,
+            Self::Drain(v) => v.iter().map(ByteSize::byte_size).sum(),
         }
     }
 }
 
-impl<T: ByteSize> ByteSize for SizedVec<T> {
+impl<T: ByteSize, L: ConstByteSize> ByteSize for SizedVec<T, L> {
     fn byte_size(&self) -> usize {
-        self.0.iter().map(ByteSize::byte_size).sum()
+        L::const_byte_size() + self.0.iter().map(ByteSize::byte_size).sum::<usize>()
     }
 }
 
-impl<T: ConstByteSize, const N: usize> ConstByteSize for Throw<T, N> {
+// Plain sequences carry a `SizeType` length prefix, the same as `SizedVec`
+impl<T: ByteSize> ByteSize for [T] {
+    fn byte_size(&self) -> usize {
+        SizeType::const_byte_size() + self.iter().map(ByteSize::byte_size).sum::<usize>()
+    }
+}
+
+impl<T: ByteSize> ByteSize for Vec<T> {
+    fn byte_size(&self) -> usize {
+        self.as_slice().byte_size()
+    }
+}
+
+impl<T: ByteSize> ByteSize for VecDeque<T> {
+    fn byte_size(&self) -> usize {
+        SizeType::const_byte_size() + self.iter().map(ByteSize::byte_size).sum::<usize>()
+    }
+}
+
+impl<T: ConstByteSize, const N: usize, const FILL: u8> ConstByteSize for Throw<T, N, FILL> {
+    fn const_byte_size() -> usize {
+        T::const_byte_size() * N
+    }
+}
+impl<T: ConstByteSize, const N: usize, const FILL: u8> ByteSize for Throw<T, N, FILL> {
+    fn byte_size(&self) -> usize {
+        <Self as ConstByteSize>::const_byte_size()
+    }
+}
+impl<T: ConstByteSize, const N: usize, const FILL: u8> ConstByteSize for Reserved<T, N, FILL> {
     fn const_byte_size() -> usize {
         T::const_byte_size() * N
     }
 }
-impl<T: ConstByteSize, const N: usize> ByteSize for Throw<T, N> {
+impl<T: ConstByteSize, const N: usize, const FILL: u8> ByteSize for Reserved<T, N, FILL> {
     fn byte_size(&self) -> usize {
         <Self as ConstByteSize>::const_byte_size()
     }
@@ -188,7 +809,13 @@ impl<T: ConstByteSize, const N: usize> ByteSize for Throw<T, N> {
 
 impl ByteSize for String {
     fn byte_size(&self) -> usize {
-        self.len()
+        SizeType::const_byte_size() + self.len()
+    }
+}
+
+impl<L: ConstByteSize> ByteSize for SizedString<L> {
+    fn byte_size(&self) -> usize {
+        L::const_byte_size() + self.0.len()
     }
 }
 
@@ -240,7 +867,7 @@ impl<T> DerefMut for SplatDrain<T> {
     }
 }
 
-impl<T> Deref for SizedVec<T> {
+impl<T, L> Deref for SizedVec<T, L> {
     type Target = [T];
 
     fn deref(&self) -> &Self::Target {
@@ -248,7 +875,21 @@ impl<T> Deref for SizedVec<T> {
     }
 }
 
-impl<T> DerefMut for SizedVec<T> {
+impl<T, L> DerefMut for SizedVec<T, L> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<L> Deref for SizedString<L> {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<L> DerefMut for SizedString<L> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.0
     }
@@ -36,7 +36,7 @@ pub fn u8_to_hex_string(b: &u8) -> [char; 2] {
 pub fn encode_as_hex_string(bytes: &[u8]) -> String {
     bytes
         .iter()
-        .map(|b| u8_to_hex_string(b))
+        .map(u8_to_hex_string)
         .fold(String::new(), |mut acc, s| {
             acc.push(s[0]);
             acc.push(s[1]);
@@ -45,6 +45,7 @@ pub fn encode_as_hex_string(bytes: &[u8]) -> String {
 }
 
 pub trait IntoHexString {
+    #[allow(clippy::wrong_self_convention)]
     fn into_hex_string(&self) -> String;
 }
 
@@ -1,3 +1,6 @@
+// lets the derives name `::bytepack` paths from inside this crate as well
+extern crate self as bytepack;
+
 pub mod base;
 pub mod hex;
 pub mod pack;
@@ -5,9 +8,12 @@ pub mod unpack;
 
 #[cfg(test)]
 mod tests {
+    use std::collections::VecDeque;
     use std::rc::Rc;
 
-    use bytepack_proc_macro::{BytePack, ByteSize, ByteUnpack};
+    use bytepack_proc_macro::{
+        BitField, BytePack, BytePackEndian, ByteSize, ByteUnpack, ByteUnpackEndian, ConstByteSize,
+    };
 
     use super::base::*;
     use super::hex::*;
@@ -55,25 +61,15 @@ mod tests {
     #[test]
     fn test_main() {
         let test_struct = new_test_struct();
-        println!(
-            "{}, {}, {}, {}",
-            &test_struct.u8_field.byte_size(),
-            &test_struct.leu32_field.byte_size(),
-            &test_struct.arr3_u32_field.byte_size(),
-            &test_struct.box2_u16_field.byte_size(),
-        );
-
-        dbg!(&test_struct);
+        assert_eq!(test_struct.u8_field.byte_size(), 1);
+        assert_eq!(test_struct.leu32_field.byte_size(), 4);
+        assert_eq!(test_struct.arr3_u32_field.byte_size(), 12);
+        assert_eq!(test_struct.box2_u16_field.byte_size(), 4);
 
         let buf = pack_value(&test_struct).unwrap();
-
-        dbg!(&buf);
-        dbg!(buf.into_hex_string());
+        assert_eq!(buf.into_hex_string(), "0508ab00ff00000001000000020000000300050006");
 
         let test_unpacked = TestStruct::unpack(&buf).unwrap();
-
-        dbg!(&test_unpacked);
-
         assert_eq!(test_struct, test_unpacked);
     }
 
@@ -86,12 +82,12 @@ mod tests {
         };
 
         let buf = pack_value(&test_pack).unwrap();
-        dbg!(&buf);
+        assert_eq!(buf, [0x05, 0x08, 0xAB, 0x00, 0xFF, 0x01, 0x02, 0xFF, 0x00]);
 
         let test_unpacked = TestUnpack::unpack(&buf).unwrap();
-
-        dbg!(test_pack);
-        dbg!(test_unpacked);
+        assert_eq!(test_unpacked.u8_field, 0x05);
+        assert_eq!(test_unpacked.u32_field, 0x08AB00FF);
+        assert_eq!(*test_unpacked.drain_vec_field, [0x0102, 0xFF00]);
     }
 
     #[test]
@@ -101,9 +97,1371 @@ mod tests {
         let buf = pack_value(&test_struct).unwrap();
 
         let test_unpacked = TestUnpack::unpack(&buf).unwrap();
+        assert_eq!(test_unpacked.u8_field, 0x05);
+        assert_eq!(test_unpacked.u32_field, 0x08AB00FF);
+        assert_eq!(*test_unpacked.drain_vec_field, [0, 1, 0, 2, 0, 3, 5, 6]);
+    }
+
+    #[derive(Debug, ByteSize, ByteUnpack)]
+    pub struct TestString {
+        pub u8_field: u8,
+        pub string_field: String,
+    }
+
+    #[test]
+    fn test_unpack_error_kind_and_offset() {
+        let buf = [0x05, 0x00, 0x00, 0x00, 0x03, b'a', 0xFF, b'c'];
+
+        let err = TestString::unpack(&buf).unwrap_err();
+
+        assert_eq!(err, UnpackError::InvalidUtf8 { offset: 6 });
+        assert_eq!(err.offset(), 6);
+    }
+
+    #[test]
+    fn test_pack_error_unsupported_variant() {
+        let splat_drain = SplatDrain::Drain(vec![1u8, 2, 3]);
+
+        let err = pack_value(&splat_drain).unwrap_err();
+
+        assert!(matches!(err, PackError::UnsupportedVariant { offset: 0, .. }));
+    }
+
+    // only unpacking needs a drain to be last, a splat packs fine in the middle
+    #[derive(Debug, ByteSize, BytePack)]
+    pub struct TestSplatMiddle {
+        pub items: SplatDrain<u8>,
+        pub end: u8,
+    }
+
+    #[test]
+    fn test_splat_drain_in_middle() {
+        let test_splat = TestSplatMiddle {
+            items: SplatDrain::Splat(vec![1, 2]),
+            end: 3,
+        };
+        assert_eq!(test_splat.byte_size(), 3);
+        assert_eq!(pack_value(&test_splat).unwrap(), [1, 2, 3]);
+
+        // a value of no bytes would drain forever
+        assert_eq!(
+            DrainVec::<Throw<u8, 0>>::unpack(&[1, 2]),
+            Err(UnpackError::ZeroSizedElement { offset: 0 })
+        );
+        assert_eq!(
+            DrainVec::<Throw<u8, 0>>::unpack_endian_from(&[1], Endian::Little),
+            Err(UnpackError::ZeroSizedElement { offset: 0 })
+        );
+        assert_eq!(DrainVec::<Throw<u8, 0>>::unpack(&[]).unwrap().len(), 0);
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct TestContainers {
+        pub string_field: String,
+        pub sized_vec_field: SizedVec<LEu16>,
+        pub throw_field: Throw<u8, 3>,
+        pub i64_field: i64,
+    }
+
+    fn assert_truncations_fail<T: ByteUnpack>(buf: &[u8]) {
+        for len in 0..buf.len() {
+            assert!(
+                T::unpack(&buf[..len]).is_err(),
+                "unpacked from a {len} byte prefix of {} bytes",
+                buf.len()
+            );
+        }
+    }
+
+    #[test]
+    fn test_unpack_truncated_input() {
+        let test_struct = pack_value(&new_test_struct()).unwrap();
+        assert_truncations_fail::<TestStruct>(&test_struct);
+
+        let test_containers = TestContainers {
+            string_field: "bytepack".to_string(),
+            sized_vec_field: SizedVec::from(vec![LEu16(1), LEu16(2), LEu16(3)]),
+            throw_field: Throw::new(),
+            i64_field: -1,
+        };
+        let buf = pack_value(&test_containers).unwrap();
+        assert_eq!(TestContainers::unpack(&buf).unwrap(), test_containers);
+        assert_truncations_fail::<TestContainers>(&buf);
+
+        // DrainVec accepts any element-aligned tail, it only must not panic
+        let buf = pack_value(&new_test_struct()).unwrap();
+        for len in 0..=buf.len() {
+            let _ = TestUnpack::unpack(&buf[..len]);
+        }
+    }
+
+    #[test]
+    fn test_unpack_untrusted_length() {
+        let buf = [0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x01];
+
+        let err = SizedVec::<u16>::unpack(&buf).unwrap_err();
+
+        assert_eq!(
+            err,
+            UnpackError::BufferTooShort {
+                offset: 6,
+                needed: 2,
+                available: 0
+            }
+        );
+        assert!(String::unpack(&buf).is_err());
+    }
+
+    #[test]
+    fn test_pack_undersized_buffer() {
+        let test_struct = new_test_struct();
+        let byte_size = test_struct.byte_size();
+
+        for len in 0..byte_size {
+            let mut buf = vec![0; len];
+            let err = test_struct.pack(&mut buf).unwrap_err();
+            assert!(matches!(err, PackError::BufferTooSmall { .. }), "{err:?}");
+        }
+
+        let mut buf = [0u8; 2];
+        let err = 0xFF00AB08u32.pack(&mut buf).unwrap_err();
+        assert_eq!(
+            err,
+            PackError::BufferTooSmall {
+                offset: 0,
+                needed: 4,
+                available: 2
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "buffer too small at offset 0: needed 4 bytes, had 2"
+        );
+
+        // u8_field and leu32_field fit, the first array element does not
+        let mut buf = [0u8; 7];
+        let err = test_struct.pack(&mut buf).unwrap_err();
+        assert_eq!(err.offset(), 5);
+    }
+
+    #[test]
+    fn test_unpack_from_bytes_consumed() {
+        let buf = pack_value(&new_test_struct()).unwrap();
+
+        let (_, consumed) = TestStruct::unpack_from(&buf).unwrap();
+        assert_eq!(consumed, buf.len());
+
+        // DrainVec consumes the whole tail and reports it in byte_size
+        let (test_unpacked, consumed) = TestUnpack::unpack_from(&buf).unwrap();
+        assert_eq!(consumed, buf.len());
+        assert_eq!(test_unpacked.drain_vec_field.len(), (buf.len() - 5) / 2);
+        assert_eq!(test_unpacked.byte_size(), buf.len());
+        assert_eq!(SplatDrain::Drain(vec![1u16, 2]).byte_size(), 4);
+
+        let mut trailing = buf.clone();
+        trailing.extend_from_slice(&[0xAA, 0xBB]);
+        let (_, consumed) = TestStruct::unpack_from(&trailing).unwrap();
+        assert_eq!(consumed, buf.len());
+    }
+
+    #[test]
+    fn test_pack_bytes_written() {
+        let test_struct = new_test_struct();
+        let mut buf = vec![0; test_struct.byte_size() + 4];
+
+        let written = test_struct.pack(&mut buf).unwrap();
+        assert_eq!(written, test_struct.byte_size());
+
+        let nested = SizedVec::from(vec![
+            SizedVec::from(vec![1u16, 2]),
+            SizedVec::from(vec![]),
+            SizedVec::from(vec![3u16]),
+        ]);
+        let mut buf = vec![0; 64];
+        let written = nested.pack(&mut buf).unwrap();
+        assert_eq!(written, 4 + (4 + 2 * 2) + 4 + (4 + 2));
+        assert_eq!(SizedVec::<SizedVec<u16>>::unpack(&buf[..written]).unwrap(), nested);
+    }
+
+    #[derive(Debug, ConstByteSize, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct PortNumber(u16);
+
+    #[derive(Debug, ConstByteSize, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct Header(u8, LEu32, PortNumber);
+
+    #[derive(Debug, ConstByteSize, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct Marker;
+
+    #[test]
+    fn test_tuple_and_unit_structs() {
+        let header = Header(0x05, LEu32(0xFF00AB08), PortNumber(0x0102));
+
+        assert_eq!(Header::const_byte_size(), 7);
+        assert_eq!(header.byte_size(), 7);
+
+        let buf = pack_value(&header).unwrap();
+        assert_eq!(buf, [0x05, 0x08, 0xAB, 0x00, 0xFF, 0x01, 0x02]);
+        assert_eq!(Header::unpack(&buf).unwrap(), header);
+
+        assert_eq!(Marker::const_byte_size(), 0);
+        assert!(pack_value(&Marker).unwrap().is_empty());
+        assert_eq!(Marker::unpack_from(&buf).unwrap(), (Marker, 0));
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    #[bytepack(tag_type = "LEu16")]
+    pub enum TestMessage {
+        Ping,
+        #[bytepack(tag = 0x10)]
+        Data(u8, SizedVec<u8>),
+        Move {
+            x: i16,
+            y: i16,
+        },
+    }
+
+    #[derive(Debug, ConstByteSize, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    #[repr(u16)]
+    pub enum TestKind {
+        A = 3,
+        B,
+        C = 0x0100,
+    }
+
+    #[test]
+    fn test_enum_tags() {
+        let buf = pack_value(&TestMessage::Ping).unwrap();
+        assert_eq!(buf, [0x00, 0x00]);
+        assert_eq!(TestMessage::unpack(&buf).unwrap(), TestMessage::Ping);
+
+        let data = TestMessage::Data(7, SizedVec::from(vec![1, 2]));
+        let buf = pack_value(&data).unwrap();
+        assert_eq!(buf, [0x10, 0x00, 0x07, 0x00, 0x00, 0x00, 0x02, 0x01, 0x02]);
+        assert_eq!(TestMessage::unpack(&buf).unwrap(), data);
+
+        // implicit tags continue from the previous variant
+        let move_ = TestMessage::Move { x: -1, y: 2 };
+        let buf = pack_value(&move_).unwrap();
+        assert_eq!(buf, [0x11, 0x00, 0xFF, 0xFF, 0x00, 0x02]);
+        assert_eq!(move_.byte_size(), 6);
+        assert_eq!(TestMessage::unpack(&buf).unwrap(), move_);
+
+        assert_eq!(TestKind::const_byte_size(), 2);
+        assert_eq!(pack_value(&TestKind::B).unwrap(), [0x00, 0x04]);
+        assert_eq!(TestKind::unpack(&[0x01, 0x00]).unwrap(), TestKind::C);
+    }
+
+    #[test]
+    fn test_enum_invalid_tag() {
+        let err = TestMessage::unpack(&[0x05, 0x00]).unwrap_err();
+        assert_eq!(
+            err,
+            UnpackError::InvalidDiscriminant {
+                offset: 0,
+                value: 5
+            }
+        );
+
+        let err = SizedVec::<TestKind>::unpack(&[0, 0, 0, 2, 0x00, 0x03, 0x00, 0x07]).unwrap_err();
+        assert_eq!(
+            err,
+            UnpackError::InvalidDiscriminant {
+                offset: 6,
+                value: 7
+            }
+        );
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub enum TestBody {
+        #[bytepack(tag = 1)]
+        Empty,
+        Value(LEu32),
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct TestFrame {
+        pub kind: u8,
+        pub len: u16,
+        #[bytepack(tag_from = "kind")]
+        pub body: TestBody,
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct TestFilledFrame(u8, #[bytepack(tag_from = "0", fill_tag)] TestBody);
+
+    #[test]
+    fn test_external_tag() {
+        let frame = TestFrame {
+            kind: 2,
+            len: 4,
+            body: TestBody::Value(LEu32(0x0102)),
+        };
+        assert_eq!(frame.byte_size(), 7);
+
+        let buf = pack_value(&frame).unwrap();
+        assert_eq!(buf, [0x02, 0x00, 0x04, 0x02, 0x01, 0x00, 0x00]);
+        assert_eq!(TestFrame::unpack(&buf).unwrap(), frame);
+
+        let empty = TestFrame::unpack(&[0x01, 0x00, 0x00]).unwrap();
+        assert_eq!(empty.body, TestBody::Empty);
+
+        let err = TestFrame::unpack(&[0x03, 0x00, 0x00]).unwrap_err();
+        assert_eq!(
+            err,
+            UnpackError::InvalidDiscriminant {
+                offset: 3,
+                value: 3
+            }
+        );
+
+        let mismatched = TestFrame {
+            kind: 1,
+            ..frame
+        };
+        let err = pack_value(&mismatched).unwrap_err();
+        assert_eq!(
+            err,
+            PackError::TagMismatch {
+                offset: 3,
+                expected: 2,
+                found: 1
+            }
+        );
+
+        // the tag field is ignored and rewritten from the variant
+        let filled = TestFilledFrame(0xFF, TestBody::Empty);
+        let buf = pack_value(&filled).unwrap();
+        assert_eq!(buf, [0x01]);
+        assert_eq!(
+            TestFilledFrame::unpack(&buf).unwrap(),
+            TestFilledFrame(0x01, TestBody::Empty)
+        );
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct TestWideFrame {
+        pub kind: u16,
+        #[bytepack(tag_from = "kind")]
+        pub body: TestBody,
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct TestNarrowFrame(i8, #[bytepack(tag_from = "0", fill_tag)] TestWideBody);
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    #[bytepack(tag_type = "u16")]
+    pub enum TestWideBody {
+        #[bytepack(tag = 0x101)]
+        Wide,
+    }
+
+    #[derive(Debug, ConstByteSize, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct TestConstFrame {
+        pub kind: u16,
+        #[bytepack(tag_from = "kind")]
+        pub body: TestKind,
+        pub end: u8,
+    }
+
+    #[test]
+    fn test_external_tag_out_of_range() {
+        // 257 does not fit the u8 tag of the body, it must not wrap around to 1
+        assert_eq!(
+            TestWideFrame::unpack(&[0x01, 0x01]),
+            Err(UnpackError::InvalidDiscriminant { offset: 2, value: 257 })
+        );
+        assert_eq!(
+            TestWideFrame::unpack(&[0x00, 0x01]).unwrap().body,
+            TestBody::Empty
+        );
+
+        assert_eq!(u8::try_from_discriminant(255), Some(255));
+        assert_eq!(u8::try_from_discriminant(256), None);
+        assert_eq!(LEi16::try_from_discriminant(-32769), None);
+        assert_eq!(u128::MAX_DISCRIMINANT, i128::MAX);
+
+        // the tag of a fieldless body lives in the header, the body adds no bytes
+        let test_const = TestConstFrame {
+            kind: 4,
+            body: TestKind::B,
+            end: 9,
+        };
+        assert_eq!(TestConstFrame::const_byte_size(), 3);
+        assert_eq!(pack_value(&test_const).unwrap(), [0, 4, 9]);
+
+        let narrow = TestNarrowFrame(0, TestWideBody::Wide);
+        assert_eq!(
+            pack_value(&narrow),
+            Err(PackError::TagMismatch { offset: 0, expected: 0x101, found: 1 })
+        );
+    }
+
+    #[derive(Debug, ConstByteSize, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct TestGeneric<P, const N: usize>
+    where
+        P: Clone,
+    {
+        pub hdr: u8,
+        pub payload: P,
+        pub trailer: [u8; N],
+    }
+
+    #[derive(Debug, ByteSize, BytePack)]
+    pub struct TestBorrowed<'a, P: ?Sized> {
+        pub hdr: u8,
+        pub payload: &'a P,
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    #[bytepack(bound(pack = "P: BytePack", unpack = "P: ByteUnpack + Default"))]
+    pub enum TestGenericEnum<P> {
+        Some(P),
+        None,
+    }
+
+    #[test]
+    fn test_generics() {
+        let generic = TestGeneric {
+            hdr: 1,
+            payload: LEu16(0x0203),
+            trailer: [4, 5],
+        };
+        assert_eq!(TestGeneric::<LEu16, 2>::const_byte_size(), 5);
+
+        let buf = pack_value(&generic).unwrap();
+        assert_eq!(buf, [1, 3, 2, 4, 5]);
+        assert_eq!(TestGeneric::unpack(&buf).unwrap(), generic);
+
+        let payload = SizedVec::<u8>::from(vec![6]);
+        let borrowed = TestBorrowed {
+            hdr: 1,
+            payload: &payload,
+        };
+        assert_eq!(pack_value(&borrowed).unwrap(), [1, 0, 0, 0, 1, 6]);
+
+        let some = TestGenericEnum::Some(String::from("a"));
+        let buf = pack_value(&some).unwrap();
+        assert_eq!(buf, [0, 0, 0, 0, 1, b'a']);
+        assert_eq!(TestGenericEnum::unpack(&buf).unwrap(), some);
+    }
+
+    mod hygiene {
+        use bytepack_proc_macro::{BytePack, ByteSize, ByteUnpack};
+
+        // none of the bytepack items are imported and these shadow the names they used to rely on
+        #[allow(dead_code)]
+        pub struct ByteSize;
+        #[allow(dead_code)]
+        pub struct PackError;
+        #[allow(dead_code)]
+        pub struct Ok;
+
+        #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+        pub struct TestHygiene {
+            pub u8_field: u8,
+            pub kind: u8,
+            #[bytepack(tag_from = "kind")]
+            pub body: super::TestBody,
+        }
+
+        #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+        #[bytepack(crate = "crate")]
+        pub enum TestCratePath {
+            A(u16),
+        }
+    }
+
+    #[test]
+    fn test_hygienic_paths() {
+        let hygiene = hygiene::TestHygiene {
+            u8_field: 1,
+            kind: 2,
+            body: TestBody::Value(LEu32(3)),
+        };
+        let buf = pack_value(&hygiene).unwrap();
+        assert_eq!(hygiene::TestHygiene::unpack(&buf).unwrap(), hygiene);
+
+        let buf = pack_value(&hygiene::TestCratePath::A(4)).unwrap();
+        assert_eq!(buf, [0, 0, 4]);
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    #[bytepack(endian = "little")]
+    pub struct TestEndian {
+        pub le_u32: u32,
+        #[bytepack(endian = "big")]
+        pub be_u16: u16,
+        pub le_arr: [i16; 2],
+        pub nested: PortNumber,
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct TestFieldEndian(#[bytepack(endian = "little")] u64, u16);
+
+    #[test]
+    fn test_endian_attribute() {
+        let test_endian = TestEndian {
+            le_u32: 0xFF00AB08,
+            be_u16: 0x0102,
+            le_arr: [1, -2],
+            nested: PortNumber(0x0304),
+        };
+
+        let buf = pack_value(&test_endian).unwrap();
+        assert_eq!(
+            buf,
+            [0x08, 0xAB, 0x00, 0xFF, 0x01, 0x02, 0x01, 0x00, 0xFE, 0xFF, 0x03, 0x04]
+        );
+        assert_eq!(TestEndian::unpack(&buf).unwrap(), test_endian);
+        assert_truncations_fail::<TestEndian>(&buf);
+
+        let test_field_endian = TestFieldEndian(1, 2);
+        let buf = pack_value(&test_field_endian).unwrap();
+        assert_eq!(buf, [1, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+        assert_eq!(TestFieldEndian::unpack(&buf).unwrap(), test_field_endian);
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, BytePackEndian, ByteUnpackEndian, PartialEq, Eq)]
+    pub struct TestIfdEntry {
+        pub tag: u16,
+        pub value: SizedVec<u32>,
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, BytePackEndian, ByteUnpackEndian, PartialEq, Eq)]
+    pub enum TestIfdValue {
+        Short(u16),
+        Entry(TestIfdEntry),
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct TestTiffHeader {
+        #[bytepack(byte_order_mark)]
+        pub mark: [u8; 2],
+        pub version: u16,
+        #[bytepack(endian = "big")]
+        pub fixed: u16,
+        pub entry: TestIfdEntry,
+    }
+
+    #[test]
+    fn test_endian_context() {
+        let mut header = TestTiffHeader {
+            mark: *b"II",
+            version: 42,
+            fixed: 1,
+            entry: TestIfdEntry {
+                tag: 0x0100,
+                value: SizedVec::from(vec![2]),
+            },
+        };
+
+        let buf = pack_value(&header).unwrap();
+        assert_eq!(buf, *b"II\x2a\x00\x00\x01\x00\x01\x01\x00\x00\x00\x02\x00\x00\x00");
+        assert_eq!(TestTiffHeader::unpack(&buf).unwrap(), header);
+        assert_truncations_fail::<TestTiffHeader>(&buf);
+
+        header.mark = *b"MM";
+        let buf = pack_value(&header).unwrap();
+        assert_eq!(buf, *b"MM\x00\x2a\x00\x01\x01\x00\x00\x00\x00\x01\x00\x00\x00\x02");
+        assert_eq!(TestTiffHeader::unpack(&buf).unwrap(), header);
+
+        let mut bad_buf = buf.clone();
+        bad_buf[..2].copy_from_slice(b"XX");
+        assert_eq!(
+            TestTiffHeader::unpack(&bad_buf),
+            Err(UnpackError::UnknownByteOrder { offset: 0 })
+        );
+        header.mark = *b"XX";
+        assert_eq!(
+            pack_value(&header),
+            Err(PackError::UnknownByteOrder { offset: 0 })
+        );
+
+        let value = TestIfdValue::Short(0x0102);
+        let mut buf = vec![0; value.byte_size()];
+        assert_eq!(value.pack_endian(&mut buf, Endian::Little), Ok(3));
+        assert_eq!(buf, [0, 0x02, 0x01]);
+        assert_eq!(
+            TestIfdValue::unpack_endian_from(&buf, Endian::Little).unwrap(),
+            (value, 3)
+        );
+        assert_eq!(
+            TestIfdValue::unpack_endian_from(&[9], Endian::Little),
+            Err(UnpackError::InvalidDiscriminant { offset: 0, value: 9 })
+        );
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct TestTiffValue {
+        #[bytepack(byte_order_mark)]
+        pub mark: [u8; 2],
+        pub kind: u8,
+        #[bytepack(tag_from = "kind")]
+        pub value: TestIfdValue,
+    }
+
+    #[test]
+    fn test_endian_context_external_tag() {
+        let mut value = TestTiffValue {
+            mark: *b"II",
+            kind: 0,
+            value: TestIfdValue::Short(0x0102),
+        };
+
+        let buf = pack_value(&value).unwrap();
+        assert_eq!(buf, *b"II\x00\x02\x01");
+        assert_eq!(TestTiffValue::unpack(&buf).unwrap(), value);
+        assert_truncations_fail::<TestTiffValue>(&buf);
+
+        value.mark = *b"MM";
+        let buf = pack_value(&value).unwrap();
+        assert_eq!(buf, *b"MM\x00\x01\x02");
+        assert_eq!(TestTiffValue::unpack(&buf).unwrap(), value);
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct TestWrappers {
+        pub be_u32: BEu32,
+        pub be_i16: BEi16,
+        pub le_i16: LEi16,
+        pub ne_u16: NEu16,
+    }
+
+    #[test]
+    fn test_wrapper_types() {
+        let test_wrappers = TestWrappers {
+            be_u32: BEu32(0xFF00AB08),
+            be_i16: BEi16(-2),
+            le_i16: LEi16(-2),
+            ne_u16: NEu16(0x0102),
+        };
+        assert_eq!(test_wrappers.byte_size(), 10);
+
+        let buf = pack_value(&test_wrappers).unwrap();
+        assert_eq!(buf[..8], [0xFF, 0x00, 0xAB, 0x08, 0xFF, 0xFE, 0xFE, 0xFF]);
+        assert_eq!(buf[8..], 0x0102u16.to_ne_bytes());
+        assert_eq!(TestWrappers::unpack(&buf).unwrap(), test_wrappers);
+
+        let mut counter = LEu32::from(1);
+        counter += 1;
+        counter = counter * LEu32(3) + 4;
+        counter <<= 1;
+        assert_eq!(counter, LEu32(20));
+        assert_eq!(u32::from(counter - 1), 19);
+        assert_eq!(!BEu16(0x00FF) & 0x0FF0, BEu16(0x0F00));
+        assert_eq!(-NEi64(5) % 3, NEi64(-2));
+        let native: i128 = BEi128(-7).into();
+        assert_eq!(native, -7);
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq)]
+    #[bytepack(endian = "little")]
+    pub struct TestFloats {
+        pub f32_field: f32,
+        #[bytepack(endian = "big")]
+        pub f64_field: f64,
+        pub le_f32: LEf32,
+        pub be_f64: BEf64,
+    }
+
+    #[test]
+    fn test_floats() {
+        let buf = pack_value(&1.5f32).unwrap();
+        assert_eq!(buf, [0x3F, 0xC0, 0x00, 0x00]);
+        assert_eq!(f32::unpack(&buf).unwrap(), 1.5);
+        let buf = pack_value(&LEf64(-2.0)).unwrap();
+        assert_eq!(buf, [0, 0, 0, 0, 0, 0, 0, 0xC0]);
+        assert_eq!(LEf64::unpack(&buf).unwrap(), LEf64(-2.0));
+        assert_eq!(f64::const_byte_size(), 8);
+
+        let test_floats = TestFloats {
+            f32_field: 1.5,
+            f64_field: -2.0,
+            le_f32: LEf32(1.5) * 2.0,
+            be_f64: -BEf64(0.25),
+        };
+        let buf = pack_value(&test_floats).unwrap();
+        assert_eq!(buf[..4], [0x00, 0x00, 0xC0, 0x3F]);
+        assert_eq!(buf[4..12], [0xC0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(buf[12..16], [0x00, 0x00, 0x40, 0x40]);
+        assert_eq!(buf[16..], [0xBF, 0xD0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(TestFloats::unpack(&buf).unwrap(), test_floats);
+
+        // NaN payloads and signalling bits survive the round trip
+        let nan32 = f32::from_bits(0x7FA0_1234);
+        let nan64 = f64::from_bits(0xFFF4_0000_DEAD_BEEF);
+        let buf = pack_value(&nan32).unwrap();
+        assert_eq!(f32::unpack(&buf).unwrap().to_bits(), nan32.to_bits());
+        let buf = pack_value(&LEf32(nan32)).unwrap();
+        assert_eq!(LEf32::unpack(&buf).unwrap().0.to_bits(), nan32.to_bits());
+        let buf = pack_value(&nan64).unwrap();
+        assert_eq!(f64::unpack(&buf).unwrap().to_bits(), nan64.to_bits());
+        let buf = pack_value(&NEf64(nan64)).unwrap();
+        assert_eq!(NEf64::unpack(&buf).unwrap().0.to_bits(), nan64.to_bits());
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    #[bytepack(endian = "little")]
+    pub struct TestBoolChar {
+        pub flag: bool,
+        pub lenient: LenientBool,
+        pub letter: char,
+        #[bytepack(endian = "little")]
+        pub le_letter: char,
+    }
+
+    #[test]
+    fn test_bool_and_char() {
+        let test_bool_char = TestBoolChar {
+            flag: true,
+            lenient: LenientBool(false),
+            letter: 'é',
+            le_letter: '🦀',
+        };
+        let buf = pack_value(&test_bool_char).unwrap();
+        assert_eq!(buf, [1, 0, 0, 0, 0, 0xE9, 0x80, 0xF9, 0x01, 0x00]);
+        assert_eq!(TestBoolChar::unpack(&buf).unwrap(), test_bool_char);
+        assert_truncations_fail::<TestBoolChar>(&buf);
+
+        assert_eq!(bool::unpack(&[0]), Ok(false));
+        assert_eq!(
+            bool::unpack(&[2]),
+            Err(UnpackError::InvalidBool { offset: 0, value: 2 })
+        );
+        assert_eq!(LenientBool::unpack(&[2]), Ok(LenientBool(true)));
+        assert_eq!(
+            TestBoolChar::unpack(&[0, 7, 0, 0, 0xD8, 0x00, 0, 0, 0, 0]),
+            Err(UnpackError::InvalidChar { offset: 2, value: 0xD800 })
+        );
+        assert_eq!(
+            char::unpack(&[0x00, 0x11, 0x00, 0x00]),
+            Err(UnpackError::InvalidChar { offset: 0, value: 0x110000 })
+        );
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct TestOptional {
+        pub flags: u8,
+        #[bytepack(if = "flags & 0x1 != 0")]
+        pub extended: Option<u16>,
+        pub trailer: Option<u8>,
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub enum TestOptionalVariant {
+        Versioned(u8, #[bytepack(if = "field_0 >= 2")] Option<LEu16>),
+    }
+
+    #[test]
+    fn test_option() {
+        assert_eq!(pack_value(&Some(0x0102u16)).unwrap(), [1, 0x01, 0x02]);
+        assert_eq!(pack_value(&None::<u16>).unwrap(), [0]);
+        assert_eq!(Option::<u16>::unpack(&[1, 0x01, 0x02]), Ok(Some(0x0102)));
+        assert_eq!(Option::<u16>::unpack(&[0]), Ok(None));
+        assert_eq!(
+            Option::<u16>::unpack(&[2, 0x01, 0x02]),
+            Err(UnpackError::InvalidDiscriminant { offset: 0, value: 2 })
+        );
+
+        let test_optional = TestOptional {
+            flags: 0x1,
+            extended: Some(0x0304),
+            trailer: Some(5),
+        };
+        assert_eq!(test_optional.byte_size(), 5);
+        let buf = pack_value(&test_optional).unwrap();
+        assert_eq!(buf, [0x1, 0x03, 0x04, 1, 5]);
+        assert_eq!(TestOptional::unpack(&buf).unwrap(), test_optional);
+        assert_truncations_fail::<TestOptional>(&buf);
+
+        let test_optional = TestOptional {
+            flags: 0x2,
+            extended: None,
+            trailer: None,
+        };
+        let buf = pack_value(&test_optional).unwrap();
+        assert_eq!(buf, [0x2, 0]);
+        assert_eq!(TestOptional::unpack(&buf).unwrap(), test_optional);
+
+        let mismatched = TestOptional {
+            flags: 0x0,
+            extended: Some(1),
+            trailer: None,
+        };
+        let mut buf = [0; 8];
+        assert_eq!(
+            mismatched.pack(&mut buf),
+            Err(PackError::ConditionMismatch { offset: 1 })
+        );
+
+        let variant = TestOptionalVariant::Versioned(2, Some(LEu16(7)));
+        let buf = pack_value(&variant).unwrap();
+        assert_eq!(buf, [0, 2, 7, 0]);
+        assert_eq!(TestOptionalVariant::unpack(&buf).unwrap(), variant);
+        assert_eq!(
+            TestOptionalVariant::unpack(&[0, 1]).unwrap(),
+            TestOptionalVariant::Versioned(1, None)
+        );
+    }
+
+    #[test]
+    fn test_tuples() {
+        type Unpack12 = (u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, bool, char);
+
+        let records = SizedVec::from(vec![(1u16, LEu32(2)), (3, LEu32(4))]);
+        let buf = pack_value(&records).unwrap();
+        assert_eq!(buf, [0, 0, 0, 2, 0, 1, 2, 0, 0, 0, 0, 3, 4, 0, 0, 0]);
+        assert_eq!(SizedVec::<(u16, LEu32)>::unpack(&buf).unwrap(), records);
+        assert_truncations_fail::<SizedVec<(u16, LEu32)>>(&buf);
+
+        assert_eq!(<(u8,)>::const_byte_size(), 1);
+        assert_eq!(<(u8, u16, LEu32)>::const_byte_size(), 7);
+        let twelve = (1u8, 2u16, 3u32, 4u64, 5u128, 6i8, 7i16, 8i32, 9i64, 10i128, true, 'c');
+        assert_eq!(twelve.byte_size(), 1 + 2 + 4 + 8 + 16 + 1 + 2 + 4 + 8 + 16 + 1 + 4);
+        let buf = pack_value(&twelve).unwrap();
+        assert_eq!(buf[..3], [1, 0, 2]);
+        assert_eq!(Unpack12::unpack(&buf).unwrap(), twelve);
+
+        let mut buf = [0; 6];
+        assert_eq!((1u16, 2u32).pack_endian(&mut buf, Endian::Little), Ok(6));
+        assert_eq!(buf, [1, 0, 2, 0, 0, 0]);
+        assert_eq!(
+            <(u16, u32)>::unpack_endian_from(&buf, Endian::Little),
+            Ok(((1, 2), 6))
+        );
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct TestSequences {
+        pub vec: Vec<u16>,
+        #[bytepack(sized)]
+        pub boxed: Box<[u8]>,
+        pub deque: VecDeque<LEu16>,
+        #[bytepack(drain)]
+        pub rest: Vec<u8>,
+    }
+
+    #[derive(Debug, ByteSize, BytePack)]
+    pub struct TestSplat {
+        #[bytepack(splat)]
+        pub items: VecDeque<u16>,
+        pub end: u8,
+    }
+
+    #[test]
+    fn test_sequences() {
+        let test_sequences = TestSequences {
+            vec: vec![1, 2],
+            boxed: Box::new([3]),
+            deque: VecDeque::from([LEu16(4)]),
+            rest: vec![5, 6],
+        };
+        assert_eq!(test_sequences.byte_size(), 8 + 5 + 6 + 2);
+        let buf = pack_value(&test_sequences).unwrap();
+        assert_eq!(
+            buf,
+            [0, 0, 0, 2, 0, 1, 0, 2, 0, 0, 0, 1, 3, 0, 0, 0, 1, 4, 0, 5, 6]
+        );
+        assert_eq!(TestSequences::unpack(&buf).unwrap(), test_sequences);
+
+        let slice: &[u16] = &[7, 8];
+        assert_eq!(pack_value(&slice).unwrap(), [0, 0, 0, 2, 0, 7, 0, 8]);
+        assert_eq!(
+            Box::<[u16]>::unpack(&[0, 0, 0, 1, 0, 9]).unwrap(),
+            Box::from([9u16])
+        );
+
+        let test_splat = TestSplat {
+            items: VecDeque::from([1, 2]),
+            end: 3,
+        };
+        assert_eq!(test_splat.byte_size(), 5);
+        assert_eq!(pack_value(&test_splat).unwrap(), [0, 1, 0, 2, 3]);
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct TestTlv {
+        pub kind: u8,
+        pub value: SizedVec<u8, u8>,
+        pub name: SizedString<LEu16>,
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, BytePackEndian, ByteUnpackEndian, PartialEq, Eq)]
+    pub struct TestSizedAttr {
+        #[bytepack(sized = "u16")]
+        pub items: Vec<u16>,
+        #[bytepack(sized = "u8")]
+        pub bytes: Box<[u8]>,
+    }
+
+    #[test]
+    fn test_length_prefix_type() {
+        let test_tlv = TestTlv {
+            kind: 1,
+            value: SizedVec::from(vec![2, 3]),
+            name: SizedString::from("ab"),
+        };
+        assert_eq!(test_tlv.byte_size(), 1 + 3 + 4);
+        let buf = pack_value(&test_tlv).unwrap();
+        assert_eq!(buf, [1, 2, 2, 3, 2, 0, b'a', b'b']);
+        assert_eq!(TestTlv::unpack(&buf).unwrap(), test_tlv);
+        assert_truncations_fail::<TestTlv>(&buf);
+
+        let too_long = SizedVec::<u8, u8>::from(vec![0; 256]);
+        let mut buf = vec![0; too_long.byte_size()];
+        assert_eq!(
+            too_long.pack(&mut buf),
+            Err(PackError::LengthOverflow { offset: 0, len: 256 })
+        );
+        let too_long = SizedString::<u8>::from("x".repeat(300));
+        let mut buf = vec![0; too_long.byte_size()];
+        assert_eq!(
+            too_long.pack(&mut buf),
+            Err(PackError::LengthOverflow { offset: 0, len: 300 })
+        );
+
+        let mut buf = [0; 4];
+        let be_len = SizedVec::<u8, BEu16>::from(vec![9, 8]);
+        assert_eq!(be_len.pack_endian(&mut buf, Endian::Little), Ok(4));
+        assert_eq!(buf, [0, 2, 9, 8]);
+        assert_eq!(
+            SizedVec::<u8, u16>::unpack_endian_from(&[2, 0, 9, 8], Endian::Little),
+            Ok((SizedVec::from(vec![9, 8]), 4))
+        );
+
+        let test_sized = TestSizedAttr {
+            items: vec![1, 2],
+            bytes: Box::new([3]),
+        };
+        assert_eq!(test_sized.byte_size(), 2 + 4 + 1 + 1);
+        let buf = pack_value(&test_sized).unwrap();
+        assert_eq!(buf, [0, 2, 0, 1, 0, 2, 1, 3]);
+        assert_eq!(TestSizedAttr::unpack(&buf).unwrap(), test_sized);
+        assert_truncations_fail::<TestSizedAttr>(&buf);
+
+        let mut buf = [0; 8];
+        assert_eq!(test_sized.pack_endian(&mut buf, Endian::Little), Ok(8));
+        assert_eq!(buf, [2, 0, 1, 0, 2, 0, 1, 3]);
+        assert_eq!(
+            TestSizedAttr::unpack_endian_from(&buf, Endian::Little),
+            Ok((test_sized, 8))
+        );
+
+        let too_long = TestSizedAttr {
+            items: vec![],
+            bytes: vec![0; 256].into_boxed_slice(),
+        };
+        let mut buf = vec![0; too_long.byte_size()];
+        assert_eq!(
+            too_long.pack(&mut buf),
+            Err(PackError::LengthOverflow { offset: 2, len: 256 })
+        );
+    }
+
+    #[derive(BytePack, ByteUnpack, ByteSize, Debug, PartialEq)]
+    struct TestCounted {
+        pub count: u16,
+        pub flags: u8,
+        #[bytepack(count = "count")]
+        pub items: Vec<u16>,
+        pub len: u8,
+        #[bytepack(bytes = "len - 1")]
+        pub payload: VecDeque<u8>,
+        pub trailer: u8,
+    }
+
+    #[derive(BytePack, ByteUnpack, ByteSize, Debug, PartialEq)]
+    struct TestFilledCount {
+        pub count: u8,
+        #[bytepack(count = "count", fill_len)]
+        pub items: Vec<u16>,
+    }
+
+    #[test]
+    fn test_length_from_field() {
+        let test_counted = TestCounted {
+            count: 2,
+            flags: 7,
+            items: vec![1, 2],
+            len: 3,
+            payload: VecDeque::from(vec![8, 9]),
+            trailer: 0xff,
+        };
+        assert_eq!(test_counted.byte_size(), 2 + 1 + 4 + 1 + 2 + 1);
+        let buf = pack_value(&test_counted).unwrap();
+        assert_eq!(buf, [0, 2, 7, 0, 1, 0, 2, 3, 8, 9, 0xff]);
+        assert_eq!(TestCounted::unpack(&buf).unwrap(), test_counted);
+        assert_truncations_fail::<TestCounted>(&buf);
+
+        let empty_payload = [0, 0, 7, 1, 0xff];
+        assert_eq!(
+            TestCounted::unpack(&empty_payload).unwrap(),
+            TestCounted {
+                count: 0,
+                flags: 7,
+                items: vec![],
+                len: 1,
+                payload: VecDeque::new(),
+                trailer: 0xff,
+            }
+        );
+        assert_eq!(
+            TestCounted::unpack(&[0, 0, 7, 0, 0xff]),
+            Err(UnpackError::LengthOverflow { offset: 4 })
+        );
+        assert_eq!(
+            TestCounted::unpack(&[0, 0, 7, 4, 8]),
+            Err(UnpackError::BufferTooShort { offset: 4, needed: 3, available: 1 })
+        );
+
+        let mismatched = TestCounted { count: 3, ..test_counted };
+        let mut buf = vec![0; mismatched.byte_size()];
+        assert_eq!(
+            mismatched.pack(&mut buf),
+            Err(PackError::LengthMismatch { offset: 3, len: 2 })
+        );
+        let zero_len = TestCounted { count: 2, len: 0, ..mismatched };
+        let mut buf = vec![0; zero_len.byte_size()];
+        assert_eq!(
+            zero_len.pack(&mut buf),
+            Err(PackError::LengthMismatch { offset: 8, len: 2 })
+        );
+
+        let test_filled = TestFilledCount {
+            count: 0,
+            items: vec![5, 6, 7],
+        };
+        let buf = pack_value(&test_filled).unwrap();
+        assert_eq!(buf, [3, 0, 5, 0, 6, 0, 7]);
+        assert_eq!(
+            TestFilledCount::unpack(&buf).unwrap(),
+            TestFilledCount { count: 3, ..test_filled }
+        );
+
+        let too_long = TestFilledCount {
+            count: 0,
+            items: vec![0; 256],
+        };
+        let mut buf = vec![0; too_long.byte_size()];
+        assert_eq!(
+            too_long.pack(&mut buf),
+            Err(PackError::LengthOverflow { offset: 0, len: 256 })
+        );
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct TestReserved {
+        pub kind: u8,
+        pub padding: Throw<u8, 2, 0xFF>,
+        pub reserved: Reserved<u16, 1>,
+        pub value: u8,
+    }
+
+    #[test]
+    fn test_reserved() {
+        let test_reserved = TestReserved {
+            kind: 1,
+            padding: Throw::new(),
+            reserved: Reserved::new(),
+            value: 2,
+        };
+        assert_eq!(test_reserved.byte_size(), 6);
+
+        // fill bytes overwrite whatever the buffer held before
+        let mut buf = [0xAA; 6];
+        assert_eq!(test_reserved.pack(&mut buf), Ok(6));
+        assert_eq!(buf, [1, 0xFF, 0xFF, 0, 0, 2]);
+        assert_eq!(TestReserved::unpack(&buf).unwrap(), test_reserved);
+        assert_truncations_fail::<TestReserved>(&buf);
+
+        // Throw ignores its bytes on unpack, Reserved rejects anything but its fill
+        assert_eq!(TestReserved::unpack(&[1, 7, 7, 0, 0, 2]).unwrap(), test_reserved);
+        let err = TestReserved::unpack(&[1, 0xFF, 0xFF, 0, 0x80, 2]).unwrap_err();
+        assert_eq!(err, UnpackError::InvalidReserved { offset: 4, value: 0x80 });
+
+        let mut buf = [0; 2];
+        assert_eq!(Reserved::<u8, 2, 0x20>::new().pack(&mut buf), Ok(2));
+        assert_eq!(buf, [0x20, 0x20]);
+        assert_eq!(
+            Reserved::<u8, 2, 0x20>::unpack_from(&[0x20, 0x20, 0]),
+            Ok((Reserved::new(), 2))
+        );
+    }
+
+    #[derive(Debug, ConstByteSize, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    #[bytepack(align = 4)]
+    pub struct TestAligned {
+        pub kind: u8,
+        #[bytepack(align = 4)]
+        pub value: u32,
+        pub flag: u8,
+        #[bytepack(align = 2)]
+        pub short: u16,
+        pub last: u8,
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    #[bytepack(pad_to = 16)]
+    pub struct TestPadded {
+        #[bytepack(pad_to = 8)]
+        pub name: SizedString<u8>,
+        pub id: u16,
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub enum TestAlignedVariant {
+        Empty,
+        Value {
+            kind: u8,
+            #[bytepack(align = 4)]
+            value: u32,
+        },
+    }
+
+    #[test]
+    fn test_align_and_pad_to() {
+        let test_aligned = TestAligned {
+            kind: 1,
+            value: 2,
+            flag: 3,
+            short: 4,
+            last: 5,
+        };
+        assert_eq!(TestAligned::const_byte_size(), 16);
+        assert_eq!(test_aligned.byte_size(), 16);
+        let mut buf = [0xAA; 16];
+        assert_eq!(test_aligned.pack(&mut buf), Ok(16));
+        assert_eq!(buf, [1, 0, 0, 0, 0, 0, 0, 2, 3, 0, 0, 4, 5, 0, 0, 0]);
+        assert_eq!(TestAligned::unpack(&buf).unwrap(), test_aligned);
+        assert_truncations_fail::<TestAligned>(&buf);
+
+        let test_padded = TestPadded {
+            name: SizedString::from("abc"),
+            id: 0x0102,
+        };
+        assert_eq!(test_padded.byte_size(), 16);
+        let buf = pack_value(&test_padded).unwrap();
+        assert_eq!(buf, [3, b'a', b'b', b'c', 0, 0, 0, 0, 1, 2, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(TestPadded::unpack(&buf).unwrap(), test_padded);
+        assert_truncations_fail::<TestPadded>(&buf);
+
+        let too_long = TestPadded {
+            name: SizedString::from("too long"),
+            id: 0,
+        };
+        // the size counts the overflowing field in full, packing it then fails on the padding
+        assert_eq!(too_long.byte_size(), 16);
+        let mut buf = vec![0; too_long.byte_size()];
+        assert_eq!(
+            too_long.pack(&mut buf),
+            Err(PackError::PaddingOverflow { offset: 9, size: 9, pad_to: 8 })
+        );
+        let mut buf = [0; 16];
+        buf[..9].copy_from_slice(b"\x08too long");
+        assert_eq!(
+            TestPadded::unpack(&buf),
+            Err(UnpackError::PaddingOverflow { offset: 9, size: 9, pad_to: 8 })
+        );
+
+        // alignment counts from the start of the variant fields, after the tag
+        let test_variant = TestAlignedVariant::Value { kind: 1, value: 2 };
+        assert_eq!(test_variant.byte_size(), 1 + 8);
+        let buf = pack_value(&test_variant).unwrap();
+        assert_eq!(buf, [1, 1, 0, 0, 0, 0, 0, 0, 2]);
+        assert_eq!(TestAlignedVariant::unpack(&buf).unwrap(), test_variant);
+        assert_truncations_fail::<TestAlignedVariant>(&buf);
+    }
+
+    #[derive(Debug, Clone, Copy, BitField, PartialEq, Eq)]
+    pub enum TestEcn {
+        NotEct,
+        Ect1,
+        Ect0,
+        Ce,
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct TestIpv4Start {
+        #[bytepack(bits = 4)]
+        pub version: u8,
+        #[bytepack(bits = 4)]
+        pub ihl: u8,
+        #[bytepack(bits = 6)]
+        pub dscp: u8,
+        #[bytepack(bits = 2)]
+        pub ecn: TestEcn,
+        pub total_length: u16,
+    }
+
+    #[derive(Debug, ConstByteSize, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    #[bytepack(bit_order = "lsb", endian = "little")]
+    pub struct TestLsbFlags {
+        #[bytepack(bits = 1)]
+        pub fin: bool,
+        #[bytepack(bits = 1)]
+        pub syn: bool,
+        #[bytepack(bits = 10)]
+        pub window: u16,
+        #[bytepack(bits = 4)]
+        pub reserved: u8,
+    }
+
+    #[derive(Debug, Clone, Copy, BitField, PartialEq, Eq)]
+    pub enum TestPriority {
+        Low = 1,
+        High = 2,
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct TestWidePriority {
+        #[bytepack(bits = 12)]
+        pub priority: TestPriority,
+        #[bytepack(bits = 4)]
+        pub spare: u8,
+    }
+
+    #[test]
+    fn test_bitfields() {
+        let test_ipv4 = TestIpv4Start {
+            version: 4,
+            ihl: 5,
+            dscp: 0x2e,
+            ecn: TestEcn::Ect0,
+            total_length: 20,
+        };
+        assert_eq!(test_ipv4.byte_size(), 4);
+        let buf = pack_value(&test_ipv4).unwrap();
+        assert_eq!(buf, [0x45, 0xba, 0, 20]);
+        assert_eq!(TestIpv4Start::unpack(&buf).unwrap(), test_ipv4);
+        assert_truncations_fail::<TestIpv4Start>(&buf);
+
+        let too_wide = TestIpv4Start { ihl: 16, ..test_ipv4 };
+        let mut buf = [0; 4];
+        assert_eq!(
+            too_wide.pack(&mut buf),
+            Err(PackError::BitFieldOverflow { offset: 0, bits: 4 })
+        );
+
+        let test_flags = TestLsbFlags {
+            fin: true,
+            syn: false,
+            window: 0x3ff,
+            reserved: 0,
+        };
+        assert_eq!(TestLsbFlags::const_byte_size(), 2);
+        let buf = pack_value(&test_flags).unwrap();
+        assert_eq!(buf, [0xfd, 0x0f]);
+        assert_eq!(TestLsbFlags::unpack(&buf).unwrap(), test_flags);
+        assert_truncations_fail::<TestLsbFlags>(&buf);
+
+        // bits beyond the tag type name no variant instead of wrapping around to one
+        assert_eq!(
+            TestWidePriority::unpack(&[0x00, 0x20]).unwrap(),
+            TestWidePriority {
+                priority: TestPriority::High,
+                spare: 0,
+            }
+        );
+        assert_eq!(
+            TestWidePriority::unpack(&[0x10, 0x20]),
+            Err(UnpackError::InvalidDiscriminant { offset: 0, value: 0x102 })
+        );
+    }
+
+    #[derive(Debug, ConstByteSize, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    #[bytepack(magic = b"\x89PNG\r\n\x1a\n")]
+    pub struct TestPngSignature {
+        #[bytepack(assert = "length == 13")]
+        pub length: u32,
+        pub chunk_type: [u8; 4],
+    }
+
+    #[derive(Debug, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    pub struct TestAssertedBits {
+        #[bytepack(bits = 4, assert = "version == 4")]
+        pub version: u8,
+        #[bytepack(bits = 4, assert = "ihl >= 5")]
+        pub ihl: u8,
+    }
+
+    #[derive(Debug, ConstByteSize, ByteSize, BytePack, ByteUnpack, PartialEq, Eq)]
+    #[bytepack(magic = b"AB", pad_to = 12)]
+    pub struct TestMagicAligned {
+        pub kind: u8,
+        #[bytepack(align = 4)]
+        pub value: u32,
+    }
+
+    #[test]
+    fn test_magic_and_assert() {
+        let test_png = TestPngSignature {
+            length: 13,
+            chunk_type: *b"IHDR",
+        };
+        assert_eq!(TestPngSignature::const_byte_size(), 16);
+        assert_eq!(test_png.byte_size(), 16);
+        let buf = pack_value(&test_png).unwrap();
+        assert_eq!(&buf[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&buf[8..], [0, 0, 0, 13, b'I', b'H', b'D', b'R']);
+        assert_eq!(TestPngSignature::unpack(&buf).unwrap(), test_png);
+        assert_truncations_fail::<TestPngSignature>(&buf);
+
+        let mut bad_magic = buf.clone();
+        bad_magic[1] = b'J';
+        assert_eq!(
+            TestPngSignature::unpack(&bad_magic),
+            Err(UnpackError::InvalidMagic { offset: 0 })
+        );
+
+        let mut bad_length = buf.clone();
+        bad_length[11] = 14;
+        let err = TestPngSignature::unpack(&bad_length).unwrap_err();
+        assert_eq!(
+            err,
+            UnpackError::AssertionFailed {
+                offset: 8,
+                field: "length",
+                assertion: "length == 13",
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "field `length` at offset 8 fails assertion `length == 13`"
+        );
+
+        let mut buf = [0; 16];
+        let bad_png = TestPngSignature { length: 14, ..test_png };
+        assert_eq!(
+            bad_png.pack(&mut buf),
+            Err(PackError::AssertionFailed {
+                offset: 8,
+                field: "length",
+                assertion: "length == 13",
+            })
+        );
+
+        let test_bits = TestAssertedBits { version: 4, ihl: 5 };
+        let buf = pack_value(&test_bits).unwrap();
+        assert_eq!(buf, [0x45]);
+        assert_eq!(TestAssertedBits::unpack(&buf).unwrap(), test_bits);
+        assert_eq!(
+            TestAssertedBits::unpack(&[0x64]),
+            Err(UnpackError::AssertionFailed {
+                offset: 0,
+                field: "version",
+                assertion: "version == 4",
+            })
+        );
 
-        dbg!(test_struct);
-        dbg!(test_unpacked);
+        // field alignment and container padding both count from the start of the magic
+        let test_aligned = TestMagicAligned { kind: 1, value: 2 };
+        assert_eq!(TestMagicAligned::const_byte_size(), 12);
+        assert_eq!(test_aligned.byte_size(), 12);
+        let buf = pack_value(&test_aligned).unwrap();
+        assert_eq!(buf, [b'A', b'B', 1, 0, 0, 0, 0, 2, 0, 0, 0, 0]);
+        assert_eq!(TestMagicAligned::unpack(&buf).unwrap(), test_aligned);
+        assert_truncations_fail::<TestMagicAligned>(&buf);
     }
 }
 
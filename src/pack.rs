@@ -1,241 +1,817 @@
-use std::{cell::RefCell, ops::Deref, rc::Rc, sync::Arc};
+use std::{cell::RefCell, collections::VecDeque, fmt, ops::Deref, rc::Rc, sync::Arc};
 
-use crate::base::{ByteSize, SizeType, SizedVec, SplatVec, ConstByteSize, Throw, SplatDrain};
+use crate::base::{
+    BitField, ByteSize, Endian, LengthPrefix, SizeType, SizedString, SizedVec, SplatVec, ConstByteSize, Throw,
+    Reserved, SplatDrain, Tagged,
+};
 
-pub fn pack_value<T: BytePack>(val: &T) -> Result<Vec<u8>, ()> {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PackError {
+    /// The output buffer cannot hold the value being packed
+    BufferTooSmall {
+        offset: usize,
+        needed: usize,
+        available: usize,
+    },
+    /// A length does not fit into its length prefix
+    LengthOverflow { offset: usize, len: usize },
+    /// The value is in a variant that has no packed representation
+    UnsupportedVariant {
+        offset: usize,
+        variant: &'static str,
+    },
+    /// An externally tagged enum disagrees with the field holding its tag
+    TagMismatch {
+        offset: usize,
+        expected: i128,
+        found: i128,
+    },
+    /// A byte order mark holds none of the values it recognizes
+    UnknownByteOrder { offset: usize },
+    /// A conditional field is present when its condition is false, or missing when it is true
+    ConditionMismatch { offset: usize },
+    /// A sequence disagrees with the field holding its count or byte length
+    LengthMismatch { offset: usize, len: usize },
+    /// Content is longer than the size it is padded to
+    PaddingOverflow { offset: usize, size: usize, pad_to: usize },
+    /// A bitfield value needs more bits than its field is wide
+    BitFieldOverflow { offset: usize, bits: u32 },
+    /// A field fails its `assert`, which unpacking it again would reject
+    AssertionFailed {
+        offset: usize,
+        field: &'static str,
+        assertion: &'static str,
+    },
+}
+
+impl PackError {
+    /// Byte offset into the output buffer where the error occurred
+    pub fn offset(&self) -> usize {
+        match self {
+            Self::BufferTooSmall { offset, .. } => *offset,
+            Self::LengthOverflow { offset, .. } => *offset,
+            Self::UnsupportedVariant { offset, .. } => *offset,
+            Self::TagMismatch { offset, .. } => *offset,
+            Self::UnknownByteOrder { offset } => *offset,
+            Self::ConditionMismatch { offset } => *offset,
+            Self::LengthMismatch { offset, .. } => *offset,
+            Self::PaddingOverflow { offset, .. } => *offset,
+            Self::BitFieldOverflow { offset, .. } => *offset,
+            Self::AssertionFailed { offset, .. } => *offset,
+        }
+    }
+
+    /// Move the error offset forward by `by` bytes,
+    /// used when a nested value was packed into a sub-slice
+    pub fn shifted(mut self, by: usize) -> Self {
+        match &mut self {
+            Self::BufferTooSmall { offset, .. } => *offset += by,
+            Self::LengthOverflow { offset, .. } => *offset += by,
+            Self::UnsupportedVariant { offset, .. } => *offset += by,
+            Self::TagMismatch { offset, .. } => *offset += by,
+            Self::UnknownByteOrder { offset } => *offset += by,
+            Self::ConditionMismatch { offset } => *offset += by,
+            Self::LengthMismatch { offset, .. } => *offset += by,
+            Self::PaddingOverflow { offset, .. } => *offset += by,
+            Self::BitFieldOverflow { offset, .. } => *offset += by,
+            Self::AssertionFailed { offset, .. } => *offset += by,
+        }
+        self
+    }
+}
+
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BufferTooSmall { offset, needed, available } => write!(
+                f,
+                "buffer too small at offset {offset}: needed {needed} bytes, had {available}"
+            ),
+            Self::LengthOverflow { offset, len } => {
+                write!(f, "length {len} overflows its length prefix at offset {offset}")
+            }
+            Self::UnsupportedVariant { offset, variant } => {
+                write!(f, "variant {variant} cannot be packed (at offset {offset})")
+            }
+            Self::TagMismatch { offset, expected, found } => write!(
+                f,
+                "tag field holds {found} but the variant at offset {offset} has tag {expected}"
+            ),
+            Self::UnknownByteOrder { offset } => {
+                write!(f, "unknown byte order mark at offset {offset}")
+            }
+            Self::ConditionMismatch { offset } => {
+                write!(f, "conditional field at offset {offset} disagrees with its condition")
+            }
+            Self::LengthMismatch { offset, len } => write!(
+                f,
+                "sequence of length {len} at offset {offset} disagrees with its length field"
+            ),
+            Self::PaddingOverflow { offset, size, pad_to } => write!(
+                f,
+                "{size} bytes of content overflow pad_to {pad_to} at offset {offset}"
+            ),
+            Self::BitFieldOverflow { offset, bits } => {
+                write!(f, "bitfield value at offset {offset} does not fit into {bits} bits")
+            }
+            Self::AssertionFailed { offset, field, assertion } => write!(
+                f,
+                "field `{field}` at offset {offset} fails assertion `{assertion}`"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PackError {}
+
+/// Fail with `BufferTooSmall` unless `buf` can hold `needed` bytes
+pub fn ensure_capacity(buf: &[u8], needed: usize) -> Result<(), PackError> {
+    if buf.len() < needed {
+        return Err(PackError::BufferTooSmall {
+            offset: 0,
+            needed,
+            available: buf.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Overwrite the first `size` bytes with `fill`, so padding never leaks what the buffer held
+pub fn pack_fill(buf: &mut [u8], size: usize, fill: u8) -> Result<usize, PackError> {
+    ensure_capacity(buf, size)?;
+    buf[..size].fill(fill);
+    Ok(size)
+}
+
+/// Zero padding after `size` bytes of content up to `pad_to` bytes, failing if the content is longer
+pub fn pack_pad_to(buf: &mut [u8], size: usize, pad_to: usize) -> Result<usize, PackError> {
+    if size > pad_to {
+        return Err(PackError::PaddingOverflow { offset: 0, size, pad_to });
+    }
+    pack_fill(buf, pad_to - size, 0)
+}
+
+/// Pack the constant `magic` bytes
+pub fn pack_magic(buf: &mut [u8], magic: &[u8]) -> Result<usize, PackError> {
+    ensure_capacity(buf, magic.len())?;
+    buf[..magic.len()].copy_from_slice(magic);
+    Ok(magic.len())
+}
+
+/// Raw bits of a bitfield value, failing if it does not fit into `bits`
+pub fn pack_bitfield<T: BitField>(value: &T, bits: u32) -> Result<u128, PackError> {
+    let raw = value.to_bits();
+    match raw.checked_shr(bits) {
+        Some(0) | None => Ok(raw),
+        Some(_) => Err(PackError::BitFieldOverflow { offset: 0, bits }),
+    }
+}
+
+/// Pack the low `bytes` bytes of a bitfield group in `endian`
+pub fn pack_bits(buf: &mut [u8], bits: u128, bytes: usize, endian: Endian) -> Result<usize, PackError> {
+    ensure_capacity(buf, bytes)?;
+    match endian {
+        Endian::Big => buf[..bytes].copy_from_slice(&bits.to_be_bytes()[16 - bytes..]),
+        Endian::Little => buf[..bytes].copy_from_slice(&bits.to_le_bytes()[..bytes]),
+    }
+    Ok(bytes)
+}
+
+/// Remaining output after `offset`, failing instead of panicking past the end
+pub fn pack_rest(buf: &mut [u8], offset: usize) -> Result<&mut [u8], PackError> {
+    let len = buf.len();
+    buf.get_mut(offset..).ok_or_else(|| PackError::BufferTooSmall {
+        offset: len,
+        needed: offset - len,
+        available: 0,
+    })
+}
+
+fn write_bytes(buf: &mut [u8], bytes: &[u8]) -> Result<usize, PackError> {
+    ensure_capacity(buf, bytes.len())?;
+    buf[..bytes.len()].copy_from_slice(bytes);
+    Ok(bytes.len())
+}
+
+/// Pack a length prefix of type `L`, failing with `LengthOverflow` if `len` does not fit
+fn pack_len<L: LengthPrefix + BytePackEndian>(
+    len: usize,
+    buf: &mut [u8],
+    endian: Endian,
+) -> Result<usize, PackError> {
+    let len_prefix = L::from_len(len).ok_or(PackError::LengthOverflow { offset: 0, len })?;
+    len_prefix.pack_endian(buf, endian)
+}
+
+/// Pack `string` as UTF-8 bytes behind a length prefix of type `L`
+fn pack_str<L: LengthPrefix + BytePackEndian>(
+    string: &str,
+    buf: &mut [u8],
+    endian: Endian,
+) -> Result<usize, PackError> {
+    let offset = pack_len::<L>(string.len(), buf, endian)?;
+    let size =
+        write_bytes(pack_rest(buf, offset)?, string.as_bytes()).map_err(|e| e.shifted(offset))?;
+    Ok(offset + size)
+}
+
+/// Pack `vals` back to back without a length prefix
+pub fn pack_splat<'a, T: BytePack + 'a>(
+    vals: impl IntoIterator<Item = &'a T>,
+    buf: &mut [u8],
+) -> Result<usize, PackError> {
+    let mut offset = 0;
+    for val in vals {
+        offset += val.pack(pack_rest(buf, offset)?).map_err(|e| e.shifted(offset))?;
+    }
+    Ok(offset)
+}
+
+/// Pack `vals` back to back without a length prefix, each in `endian`
+pub fn pack_splat_endian<'a, T: BytePackEndian + 'a>(
+    vals: impl IntoIterator<Item = &'a T>,
+    buf: &mut [u8],
+    endian: Endian,
+) -> Result<usize, PackError> {
+    let mut offset = 0;
+    for val in vals {
+        offset += val
+            .pack_endian(pack_rest(buf, offset)?, endian)
+            .map_err(|e| e.shifted(offset))?;
+    }
+    Ok(offset)
+}
+
+/// Pack `vals` behind a length prefix of type `L`
+pub fn pack_sized<'a, T: BytePack + 'a, L: LengthPrefix + BytePackEndian>(
+    vals: impl ExactSizeIterator<Item = &'a T>,
+    buf: &mut [u8],
+) -> Result<usize, PackError> {
+    let offset = pack_len::<L>(vals.len(), buf, Endian::Big)?;
+    let size = pack_splat(vals, pack_rest(buf, offset)?).map_err(|e| e.shifted(offset))?;
+    Ok(offset + size)
+}
+
+/// Pack `vals` behind a length prefix of type `L`, the prefix and each value in `endian`
+pub fn pack_sized_endian<'a, T: BytePackEndian + 'a, L: LengthPrefix + BytePackEndian>(
+    vals: impl ExactSizeIterator<Item = &'a T>,
+    buf: &mut [u8],
+    endian: Endian,
+) -> Result<usize, PackError> {
+    let offset = pack_len::<L>(vals.len(), buf, endian)?;
+    let size = pack_splat_endian(vals, pack_rest(buf, offset)?, endian)
+        .map_err(|e| e.shifted(offset))?;
+    Ok(offset + size)
+}
+
+pub fn pack_value<T: BytePack>(val: &T) -> Result<Vec<u8>, PackError> {
     let mut buf_vec = vec![0; val.byte_size()];
     let buf = &mut buf_vec[..];
-    let _residue = val.pack(buf)?;
+    val.pack(buf)?;
     Ok(buf_vec)
 }
 
 pub trait BytePack: ByteSize {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()>;
+    /// Pack the value into the start of `buf`, returning the number of bytes written
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError>;
+}
+
+/// Pack an enum without its tag, for when the tag is stored elsewhere
+pub trait UntaggedBytePack: Tagged {
+    fn pack_untagged(&self, buf: &mut [u8]) -> Result<usize, PackError>;
+}
+
+/// Pack an enum without its tag in a byte order chosen by the caller,
+/// for a tag stored elsewhere in a `BytePackEndian` container
+pub trait UntaggedBytePackEndian: Tagged {
+    fn pack_untagged_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError>;
+}
+
+/// Pack with a byte order chosen by the caller instead of by the type.
+///
+/// Containers pass the byte order on to their elements and length prefixes,
+/// types with a fixed byte order such as `LEu16` ignore it.
+pub trait BytePackEndian: ByteSize {
+    fn pack_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError>;
 }
 
 impl BytePack for u8 {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
-        buf[0] = *self;
-        Ok(())
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        write_bytes(buf, &[*self])
     }
 }
 
 // Default is Network (Big Endian) byte order
 impl BytePack for u16 {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
-        let be_bytes = self.to_be_bytes();
-        for (i, b) in be_bytes.iter().enumerate() {
-            buf[i] = *b;
-        }
-        Ok(())
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        write_bytes(buf, &self.to_be_bytes())
     }
 }
 
 // Default is Network (Big Endian) byte order
 impl BytePack for u32 {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
-        let be_bytes = self.to_be_bytes();
-        for (i, b) in be_bytes.iter().enumerate() {
-            buf[i] = *b;
-        }
-        Ok(())
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        write_bytes(buf, &self.to_be_bytes())
     }
 }
 
 // Default is Network (Big Endian) byte order
 impl BytePack for u64 {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
-        let be_bytes = self.to_be_bytes();
-        for (i, b) in be_bytes.iter().enumerate() {
-            buf[i] = *b;
-        }
-        Ok(())
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        write_bytes(buf, &self.to_be_bytes())
     }
 }
 
 // Default is Network (Big Endian) byte order
 impl BytePack for u128 {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
-        let be_bytes = self.to_be_bytes();
-        for (i, b) in be_bytes.iter().enumerate() {
-            buf[i] = *b;
-        }
-        Ok(())
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        write_bytes(buf, &self.to_be_bytes())
     }
 }
 
 impl BytePack for i8 {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
-        buf[0] = *self as u8;
-        Ok(())
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        write_bytes(buf, &[*self as u8])
     }
 }
 
 // Default is Network (Big Endian) byte order
 impl BytePack for i16 {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
-        let be_bytes = self.to_be_bytes();
-        for (i, b) in be_bytes.iter().enumerate() {
-            buf[i] = *b;
-        }
-        Ok(())
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        write_bytes(buf, &self.to_be_bytes())
     }
 }
 
 // Default is Network (Big Endian) byte order
 impl BytePack for i32 {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
-        let be_bytes = self.to_be_bytes();
-        for (i, b) in be_bytes.iter().enumerate() {
-            buf[i] = *b;
-        }
-        Ok(())
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        write_bytes(buf, &self.to_be_bytes())
     }
 }
 
 // Default is Network (Big Endian) byte order
 impl BytePack for i64 {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
-        let be_bytes = self.to_be_bytes();
-        for (i, b) in be_bytes.iter().enumerate() {
-            buf[i] = *b;
-        }
-        Ok(())
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        write_bytes(buf, &self.to_be_bytes())
     }
 }
 
 // Default is Network (Big Endian) byte order
 impl BytePack for i128 {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
-        let be_bytes = self.to_be_bytes();
-        for (i, b) in be_bytes.iter().enumerate() {
-            buf[i] = *b;
-        }
-        Ok(())
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        write_bytes(buf, &self.to_be_bytes())
+    }
+}
+
+// Default is Network (Big Endian) byte order
+impl BytePack for f32 {
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        write_bytes(buf, &self.to_be_bytes())
     }
 }
 
-macro_rules! imp_pack_for_le_num {
-    ($le_u_type: ty) => {
-        impl BytePack for $le_u_type {
-            fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
-                let le_bytes = self.0.to_le_bytes();
-                for (i, b) in le_bytes.iter().enumerate() {
-                    buf[i] = *b;
+// Default is Network (Big Endian) byte order
+impl BytePack for f64 {
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        write_bytes(buf, &self.to_be_bytes())
+    }
+}
+
+macro_rules! imp_pack_for_wrapped_num {
+    ($wrapped_type: ty, $to_bytes: ident) => {
+        impl BytePack for $wrapped_type {
+            fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+                write_bytes(buf, &self.0.$to_bytes())
+            }
+        }
+    };
+}
+
+imp_pack_for_wrapped_num!(crate::base::LEu16, to_le_bytes);
+imp_pack_for_wrapped_num!(crate::base::LEu32, to_le_bytes);
+imp_pack_for_wrapped_num!(crate::base::LEu64, to_le_bytes);
+imp_pack_for_wrapped_num!(crate::base::LEu128, to_le_bytes);
+imp_pack_for_wrapped_num!(crate::base::LEi16, to_le_bytes);
+imp_pack_for_wrapped_num!(crate::base::LEi32, to_le_bytes);
+imp_pack_for_wrapped_num!(crate::base::LEi64, to_le_bytes);
+imp_pack_for_wrapped_num!(crate::base::LEi128, to_le_bytes);
+imp_pack_for_wrapped_num!(crate::base::LEf32, to_le_bytes);
+imp_pack_for_wrapped_num!(crate::base::LEf64, to_le_bytes);
+imp_pack_for_wrapped_num!(crate::base::BEu16, to_be_bytes);
+imp_pack_for_wrapped_num!(crate::base::BEu32, to_be_bytes);
+imp_pack_for_wrapped_num!(crate::base::BEu64, to_be_bytes);
+imp_pack_for_wrapped_num!(crate::base::BEu128, to_be_bytes);
+imp_pack_for_wrapped_num!(crate::base::BEi16, to_be_bytes);
+imp_pack_for_wrapped_num!(crate::base::BEi32, to_be_bytes);
+imp_pack_for_wrapped_num!(crate::base::BEi64, to_be_bytes);
+imp_pack_for_wrapped_num!(crate::base::BEi128, to_be_bytes);
+imp_pack_for_wrapped_num!(crate::base::BEf32, to_be_bytes);
+imp_pack_for_wrapped_num!(crate::base::BEf64, to_be_bytes);
+imp_pack_for_wrapped_num!(crate::base::NEu16, to_ne_bytes);
+imp_pack_for_wrapped_num!(crate::base::NEu32, to_ne_bytes);
+imp_pack_for_wrapped_num!(crate::base::NEu64, to_ne_bytes);
+imp_pack_for_wrapped_num!(crate::base::NEu128, to_ne_bytes);
+imp_pack_for_wrapped_num!(crate::base::NEi16, to_ne_bytes);
+imp_pack_for_wrapped_num!(crate::base::NEi32, to_ne_bytes);
+imp_pack_for_wrapped_num!(crate::base::NEi64, to_ne_bytes);
+imp_pack_for_wrapped_num!(crate::base::NEi128, to_ne_bytes);
+imp_pack_for_wrapped_num!(crate::base::NEf32, to_ne_bytes);
+imp_pack_for_wrapped_num!(crate::base::NEf64, to_ne_bytes);
+
+macro_rules! imp_pack_endian_for_wrapped_num {
+    ($wrapped_type: ty) => {
+        impl BytePackEndian for $wrapped_type {
+            fn pack_endian(&self, buf: &mut [u8], _endian: Endian) -> Result<usize, PackError> {
+                self.pack(buf)
+            }
+        }
+    };
+}
+
+imp_pack_endian_for_wrapped_num!(crate::base::LEu16);
+imp_pack_endian_for_wrapped_num!(crate::base::LEu32);
+imp_pack_endian_for_wrapped_num!(crate::base::LEu64);
+imp_pack_endian_for_wrapped_num!(crate::base::LEu128);
+imp_pack_endian_for_wrapped_num!(crate::base::LEi16);
+imp_pack_endian_for_wrapped_num!(crate::base::LEi32);
+imp_pack_endian_for_wrapped_num!(crate::base::LEi64);
+imp_pack_endian_for_wrapped_num!(crate::base::LEi128);
+imp_pack_endian_for_wrapped_num!(crate::base::LEf32);
+imp_pack_endian_for_wrapped_num!(crate::base::LEf64);
+imp_pack_endian_for_wrapped_num!(crate::base::BEu16);
+imp_pack_endian_for_wrapped_num!(crate::base::BEu32);
+imp_pack_endian_for_wrapped_num!(crate::base::BEu64);
+imp_pack_endian_for_wrapped_num!(crate::base::BEu128);
+imp_pack_endian_for_wrapped_num!(crate::base::BEi16);
+imp_pack_endian_for_wrapped_num!(crate::base::BEi32);
+imp_pack_endian_for_wrapped_num!(crate::base::BEi64);
+imp_pack_endian_for_wrapped_num!(crate::base::BEi128);
+imp_pack_endian_for_wrapped_num!(crate::base::BEf32);
+imp_pack_endian_for_wrapped_num!(crate::base::BEf64);
+imp_pack_endian_for_wrapped_num!(crate::base::NEu16);
+imp_pack_endian_for_wrapped_num!(crate::base::NEu32);
+imp_pack_endian_for_wrapped_num!(crate::base::NEu64);
+imp_pack_endian_for_wrapped_num!(crate::base::NEu128);
+imp_pack_endian_for_wrapped_num!(crate::base::NEi16);
+imp_pack_endian_for_wrapped_num!(crate::base::NEi32);
+imp_pack_endian_for_wrapped_num!(crate::base::NEi64);
+imp_pack_endian_for_wrapped_num!(crate::base::NEi128);
+imp_pack_endian_for_wrapped_num!(crate::base::NEf32);
+imp_pack_endian_for_wrapped_num!(crate::base::NEf64);
+
+macro_rules! imp_pack_endian_for_num {
+    ($num_type: ty) => {
+        impl BytePackEndian for $num_type {
+            fn pack_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError> {
+                match endian {
+                    Endian::Big => write_bytes(buf, &self.to_be_bytes()),
+                    Endian::Little => write_bytes(buf, &self.to_le_bytes()),
                 }
-                Ok(())
             }
         }
     };
 }
 
-imp_pack_for_le_num!(crate::base::LEu16);
-imp_pack_for_le_num!(crate::base::LEu32);
-imp_pack_for_le_num!(crate::base::LEu64);
-imp_pack_for_le_num!(crate::base::LEu128);
-imp_pack_for_le_num!(crate::base::LEi16);
-imp_pack_for_le_num!(crate::base::LEi32);
-imp_pack_for_le_num!(crate::base::LEi64);
-imp_pack_for_le_num!(crate::base::LEi128);
+imp_pack_endian_for_num!(u8);
+imp_pack_endian_for_num!(u16);
+imp_pack_endian_for_num!(u32);
+imp_pack_endian_for_num!(u64);
+imp_pack_endian_for_num!(u128);
+imp_pack_endian_for_num!(i8);
+imp_pack_endian_for_num!(i16);
+imp_pack_endian_for_num!(i32);
+imp_pack_endian_for_num!(i64);
+imp_pack_endian_for_num!(i128);
+imp_pack_endian_for_num!(f32);
+imp_pack_endian_for_num!(f64);
+
+impl BytePack for bool {
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        write_bytes(buf, &[*self as u8])
+    }
+}
+
+impl BytePack for crate::base::LenientBool {
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        self.0.pack(buf)
+    }
+}
+
+// Encoded as the u32 scalar value, Network (Big Endian) byte order by default
+impl BytePack for char {
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        u32::from(*self).pack(buf)
+    }
+}
+
+impl BytePackEndian for bool {
+    fn pack_endian(&self, buf: &mut [u8], _endian: Endian) -> Result<usize, PackError> {
+        self.pack(buf)
+    }
+}
+
+impl BytePackEndian for crate::base::LenientBool {
+    fn pack_endian(&self, buf: &mut [u8], _endian: Endian) -> Result<usize, PackError> {
+        self.pack(buf)
+    }
+}
+
+impl BytePackEndian for char {
+    fn pack_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError> {
+        u32::from(*self).pack_endian(buf, endian)
+    }
+}
 
 impl<T: BytePack, const N: usize> BytePack for [T; N] {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
-        let mut buf = buf;
-        for i in 0..N {
-            let val = &self[i];
-            let _res = val.pack(buf)?;
-            let byte_size = val.byte_size();
-            buf = &mut buf[byte_size..];
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        let mut offset = 0;
+        for val in self {
+            offset += val.pack(pack_rest(buf, offset)?).map_err(|e| e.shifted(offset))?;
+        }
+        Ok(offset)
+    }
+}
+
+impl<T: BytePackEndian, const N: usize> BytePackEndian for [T; N] {
+    fn pack_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError> {
+        let mut offset = 0;
+        for val in self {
+            offset += val
+                .pack_endian(pack_rest(buf, offset)?, endian)
+                .map_err(|e| e.shifted(offset))?;
         }
-        Ok(())
+        Ok(offset)
     }
 }
 
-impl<T: BytePack> BytePack for Box<T> {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
+// Elements are packed one after another, the same as the fields of a derived struct
+macro_rules! imp_pack_for_tuple {
+    ($($elem_type: ident $index: tt),+) => {
+        impl<$($elem_type: BytePack),+> BytePack for ($($elem_type,)+) {
+            fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+                let mut offset = 0;
+                $(
+                    offset += self.$index
+                        .pack(pack_rest(buf, offset)?)
+                        .map_err(|e| e.shifted(offset))?;
+                )+
+                Ok(offset)
+            }
+        }
+        impl<$($elem_type: BytePackEndian),+> BytePackEndian for ($($elem_type,)+) {
+            fn pack_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError> {
+                let mut offset = 0;
+                $(
+                    offset += self.$index
+                        .pack_endian(pack_rest(buf, offset)?, endian)
+                        .map_err(|e| e.shifted(offset))?;
+                )+
+                Ok(offset)
+            }
+        }
+    };
+}
+
+imp_pack_for_tuple!(A 0);
+imp_pack_for_tuple!(A 0, B 1);
+imp_pack_for_tuple!(A 0, B 1, C 2);
+imp_pack_for_tuple!(A 0, B 1, C 2, D 3);
+imp_pack_for_tuple!(A 0, B 1, C 2, D 3, E 4);
+imp_pack_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+imp_pack_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+imp_pack_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+imp_pack_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8);
+imp_pack_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9);
+imp_pack_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10);
+imp_pack_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10, L 11);
+
+impl<T: BytePack + ?Sized> BytePack for &T {
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        (**self).pack(buf)
+    }
+}
+
+impl<T: BytePack + ?Sized> BytePack for Box<T> {
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
         self.deref().pack(buf)
     }
 }
 
 impl<T: BytePack> BytePack for Rc<T> {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
         self.deref().pack(buf)
     }
 }
 
 impl<T: BytePack> BytePack for RefCell<T> {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
         self.borrow().pack(buf)
     }
 }
 
 impl<T: BytePack> BytePack for Arc<T> {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
         self.deref().pack(buf)
     }
 }
 
+impl<T: BytePack> BytePack for Option<T> {
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        let Some(val) = self else {
+            return false.pack(buf);
+        };
+        let offset = true.pack(buf)?;
+        let size = val.pack(pack_rest(buf, offset)?).map_err(|e| e.shifted(offset))?;
+        Ok(offset + size)
+    }
+}
+
 impl<T: BytePack> BytePack for SplatVec<T> {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
-        let mut buf = buf;
-        for val in &self.0 {
-            let _res = val.pack(buf)?;
-            let byte_size = val.byte_size();
-            buf = &mut buf[byte_size..];
-        }
-        Ok(())
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        pack_splat(&self.0, buf)
     }
 }
 
 impl<T: BytePack> BytePack for SplatDrain<T> {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
         let Self::Splat(vec) = self else {
-            return Err(());
+            return Err(PackError::UnsupportedVariant {
+                offset: 0,
+                variant: "SplatDrain::Drain",
+            });
         };
-        
-        let mut buf = buf;
-        for val in vec {
-            let _res = val.pack(buf)?;
-            let byte_size = val.byte_size();
-            buf = &mut buf[byte_size..];
-        }
-        Ok(())
+        pack_splat(vec, buf)
     }
 }
 
-impl<T: BytePack> BytePack for SizedVec<T> {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
-        let mut buf = buf;
+impl<T: BytePack, L: LengthPrefix + BytePackEndian> BytePack for SizedVec<T, L> {
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        let offset = pack_len::<L>(self.0.len(), buf, Endian::Big)?;
+        let size = pack_splat(&self.0, pack_rest(buf, offset)?).map_err(|e| e.shifted(offset))?;
+        Ok(offset + size)
+    }
+}
 
-        let len = self.0.len() as SizeType;
-        let _res = len.pack(buf)?;
-        buf = &mut buf[len.byte_size()..];
+impl<T: BytePack> BytePack for [T] {
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        let offset = pack_len::<SizeType>(self.len(), buf, Endian::Big)?;
+        let size = pack_splat(self, pack_rest(buf, offset)?).map_err(|e| e.shifted(offset))?;
+        Ok(offset + size)
+    }
+}
 
-        for val in &self.0 {
-            let _res = val.pack(buf)?;
-            let byte_size = val.byte_size();
-            buf = &mut buf[byte_size..];
-        }
-        Ok(())
+impl<T: BytePack> BytePack for Vec<T> {
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        self.as_slice().pack(buf)
+    }
+}
+
+impl<T: BytePack> BytePack for VecDeque<T> {
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        let offset = pack_len::<SizeType>(self.len(), buf, Endian::Big)?;
+        let size = pack_splat(self, pack_rest(buf, offset)?).map_err(|e| e.shifted(offset))?;
+        Ok(offset + size)
     }
 }
 
-impl<T: BytePack + ConstByteSize, const N: usize> BytePack for Throw<T, N> {
-    fn pack(&self, _buf: &mut [u8]) -> Result<(), ()> {
-        Ok(())
+impl<T: BytePack + ConstByteSize, const N: usize, const FILL: u8> BytePack for Throw<T, N, FILL> {
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        pack_fill(buf, Self::const_byte_size(), FILL)
+    }
+}
+
+impl<T: BytePack + ConstByteSize, const N: usize, const FILL: u8> BytePack for Reserved<T, N, FILL> {
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        pack_fill(buf, Self::const_byte_size(), FILL)
     }
 }
 
 impl BytePack for String {
-    fn pack(&self, buf: &mut [u8]) -> Result<(), ()> {
-        let mut buf = buf;
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        pack_str::<SizeType>(self, buf, Endian::Big)
+    }
+}
+
+impl<L: LengthPrefix + BytePackEndian> BytePack for SizedString<L> {
+    fn pack(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        pack_str::<L>(&self.0, buf, Endian::Big)
+    }
+}
+
+impl<T: BytePackEndian + ?Sized> BytePackEndian for &T {
+    fn pack_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError> {
+        (**self).pack_endian(buf, endian)
+    }
+}
+
+impl<T: BytePackEndian + ?Sized> BytePackEndian for Box<T> {
+    fn pack_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError> {
+        self.deref().pack_endian(buf, endian)
+    }
+}
+
+impl<T: BytePackEndian> BytePackEndian for Rc<T> {
+    fn pack_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError> {
+        self.deref().pack_endian(buf, endian)
+    }
+}
+
+impl<T: BytePackEndian> BytePackEndian for RefCell<T> {
+    fn pack_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError> {
+        self.borrow().pack_endian(buf, endian)
+    }
+}
+
+impl<T: BytePackEndian> BytePackEndian for Arc<T> {
+    fn pack_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError> {
+        self.deref().pack_endian(buf, endian)
+    }
+}
+
+impl<T: BytePackEndian> BytePackEndian for Option<T> {
+    fn pack_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError> {
+        let Some(val) = self else {
+            return false.pack(buf);
+        };
+        let offset = true.pack(buf)?;
+        let size = val
+            .pack_endian(pack_rest(buf, offset)?, endian)
+            .map_err(|e| e.shifted(offset))?;
+        Ok(offset + size)
+    }
+}
 
-        let len = self.byte_size() as SizeType;
-        let _res = len.pack(buf)?;
-        buf = &mut buf[len.byte_size()..];
+impl<T: BytePackEndian> BytePackEndian for SplatVec<T> {
+    fn pack_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError> {
+        pack_splat_endian(&self.0, buf, endian)
+    }
+}
 
-        (&mut buf[..len as usize]).clone_from_slice(self.as_bytes());
+impl<T: BytePackEndian> BytePackEndian for SplatDrain<T> {
+    fn pack_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError> {
+        let Self::Splat(vec) = self else {
+            return Err(PackError::UnsupportedVariant {
+                offset: 0,
+                variant: "SplatDrain::Drain",
+            });
+        };
+        pack_splat_endian(vec, buf, endian)
+    }
+}
+
+impl<T: BytePackEndian, L: LengthPrefix + BytePackEndian> BytePackEndian for SizedVec<T, L> {
+    fn pack_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError> {
+        let offset = pack_len::<L>(self.0.len(), buf, endian)?;
+        let size = pack_splat_endian(&self.0, pack_rest(buf, offset)?, endian)
+            .map_err(|e| e.shifted(offset))?;
+        Ok(offset + size)
+    }
+}
+
+impl<T: BytePackEndian> BytePackEndian for [T] {
+    fn pack_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError> {
+        let offset = pack_len::<SizeType>(self.len(), buf, endian)?;
+        let size = pack_splat_endian(self, pack_rest(buf, offset)?, endian)
+            .map_err(|e| e.shifted(offset))?;
+        Ok(offset + size)
+    }
+}
+
+impl<T: BytePackEndian> BytePackEndian for Vec<T> {
+    fn pack_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError> {
+        self.as_slice().pack_endian(buf, endian)
+    }
+}
+
+impl<T: BytePackEndian> BytePackEndian for VecDeque<T> {
+    fn pack_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError> {
+        let offset = pack_len::<SizeType>(self.len(), buf, endian)?;
+        let size = pack_splat_endian(self, pack_rest(buf, offset)?, endian)
+            .map_err(|e| e.shifted(offset))?;
+        Ok(offset + size)
+    }
+}
+
+impl<T: BytePack + ConstByteSize, const N: usize, const FILL: u8> BytePackEndian for Throw<T, N, FILL> {
+    fn pack_endian(&self, buf: &mut [u8], _endian: Endian) -> Result<usize, PackError> {
+        self.pack(buf)
+    }
+}
+
+impl<T: BytePack + ConstByteSize, const N: usize, const FILL: u8> BytePackEndian for Reserved<T, N, FILL> {
+    fn pack_endian(&self, buf: &mut [u8], _endian: Endian) -> Result<usize, PackError> {
+        self.pack(buf)
+    }
+}
+
+impl BytePackEndian for String {
+    fn pack_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError> {
+        pack_str::<SizeType>(self, buf, endian)
+    }
+}
 
-        Ok(())
+impl<L: LengthPrefix + BytePackEndian> BytePackEndian for SizedString<L> {
+    fn pack_endian(&self, buf: &mut [u8], endian: Endian) -> Result<usize, PackError> {
+        pack_str::<L>(&self.0, buf, endian)
     }
 }
@@ -1,193 +1,933 @@
 use std::array;
+use std::collections::VecDeque;
+use std::fmt;
 use std::sync::Arc;
 use std::{cell::RefCell, rc::Rc};
 
-use crate::base::{ByteSize, ConstByteSize, DrainVec, SizedVec, SizeType, Throw, SplatDrain};
+use crate::base::{
+    BitField, ByteSize, ConstByteSize, Endian, DrainVec, LengthPrefix, SizedString, SizedVec, SizeType, Throw,
+    Reserved, SplatDrain, Tagged,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnpackError {
+    /// The input ended before the value was complete
+    BufferTooShort {
+        offset: usize,
+        needed: usize,
+        available: usize,
+    },
+    /// A string payload is not valid UTF-8
+    InvalidUtf8 { offset: usize },
+    /// A tag value does not map to any known variant
+    InvalidDiscriminant { offset: usize, value: i128 },
+    /// A decoded length does not fit into memory addressing
+    LengthOverflow { offset: usize },
+    /// A byte order mark holds none of the values it recognizes
+    UnknownByteOrder { offset: usize },
+    /// A `bool` byte is neither 0 nor 1
+    InvalidBool { offset: usize, value: u8 },
+    /// A `char` is a surrogate or beyond the last Unicode scalar value
+    InvalidChar { offset: usize, value: u32 },
+    /// A reserved byte differs from its fill value
+    InvalidReserved { offset: usize, value: u8 },
+    /// Content is longer than the size it is padded to
+    PaddingOverflow { offset: usize, size: usize, pad_to: usize },
+    /// The input does not start with the magic bytes of the type
+    InvalidMagic { offset: usize },
+    /// A drained value took no bytes, so the rest of the input would never run out
+    ZeroSizedElement { offset: usize },
+    /// A field fails its `assert`
+    AssertionFailed {
+        offset: usize,
+        field: &'static str,
+        assertion: &'static str,
+    },
+}
+
+impl UnpackError {
+    /// Byte offset into the input buffer where the error occurred
+    pub fn offset(&self) -> usize {
+        match self {
+            Self::BufferTooShort { offset, .. } => *offset,
+            Self::InvalidUtf8 { offset } => *offset,
+            Self::InvalidDiscriminant { offset, .. } => *offset,
+            Self::LengthOverflow { offset } => *offset,
+            Self::UnknownByteOrder { offset } => *offset,
+            Self::InvalidBool { offset, .. } => *offset,
+            Self::InvalidChar { offset, .. } => *offset,
+            Self::InvalidReserved { offset, .. } => *offset,
+            Self::PaddingOverflow { offset, .. } => *offset,
+            Self::InvalidMagic { offset } => *offset,
+            Self::ZeroSizedElement { offset } => *offset,
+            Self::AssertionFailed { offset, .. } => *offset,
+        }
+    }
+
+    /// Move the error offset forward by `by` bytes,
+    /// used when a nested value was unpacked from a sub-slice
+    pub fn shifted(mut self, by: usize) -> Self {
+        match &mut self {
+            Self::BufferTooShort { offset, .. } => *offset += by,
+            Self::InvalidUtf8 { offset } => *offset += by,
+            Self::InvalidDiscriminant { offset, .. } => *offset += by,
+            Self::LengthOverflow { offset } => *offset += by,
+            Self::UnknownByteOrder { offset } => *offset += by,
+            Self::InvalidBool { offset, .. } => *offset += by,
+            Self::InvalidChar { offset, .. } => *offset += by,
+            Self::InvalidReserved { offset, .. } => *offset += by,
+            Self::PaddingOverflow { offset, .. } => *offset += by,
+            Self::InvalidMagic { offset } => *offset += by,
+            Self::ZeroSizedElement { offset } => *offset += by,
+            Self::AssertionFailed { offset, .. } => *offset += by,
+        }
+        self
+    }
+}
+
+impl fmt::Display for UnpackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BufferTooShort { offset, needed, available } => write!(
+                f,
+                "buffer too short at offset {offset}: needed {needed} bytes, had {available}"
+            ),
+            Self::InvalidUtf8 { offset } => write!(f, "invalid utf-8 at offset {offset}"),
+            Self::InvalidDiscriminant { offset, value } => {
+                write!(f, "invalid discriminant {value} at offset {offset}")
+            }
+            Self::LengthOverflow { offset } => write!(f, "length overflow at offset {offset}"),
+            Self::UnknownByteOrder { offset } => {
+                write!(f, "unknown byte order mark at offset {offset}")
+            }
+            Self::InvalidBool { offset, value } => {
+                write!(f, "invalid bool {value} at offset {offset}")
+            }
+            Self::InvalidChar { offset, value } => {
+                write!(f, "invalid char {value:#x} at offset {offset}")
+            }
+            Self::InvalidReserved { offset, value } => {
+                write!(f, "invalid reserved byte {value:#04x} at offset {offset}")
+            }
+            Self::PaddingOverflow { offset, size, pad_to } => write!(
+                f,
+                "{size} bytes of content overflow pad_to {pad_to} at offset {offset}"
+            ),
+            Self::InvalidMagic { offset } => write!(f, "invalid magic bytes at offset {offset}"),
+            Self::ZeroSizedElement { offset } => {
+                write!(f, "zero-sized element cannot drain the input at offset {offset}")
+            }
+            Self::AssertionFailed { offset, field, assertion } => write!(
+                f,
+                "field `{field}` at offset {offset} fails assertion `{assertion}`"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for UnpackError {}
+
+/// Fail with `BufferTooShort` unless `buf` holds at least `needed` bytes
+pub fn ensure_len(buf: &[u8], needed: usize) -> Result<(), UnpackError> {
+    if buf.len() < needed {
+        return Err(UnpackError::BufferTooShort {
+            offset: 0,
+            needed,
+            available: buf.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Remaining input after `offset`, failing instead of panicking past the end
+pub fn unpack_rest(buf: &[u8], offset: usize) -> Result<&[u8], UnpackError> {
+    buf.get(offset..).ok_or_else(|| UnpackError::BufferTooShort {
+        offset: buf.len(),
+        needed: offset - buf.len(),
+        available: 0,
+    })
+}
+
+/// Skip `padding` bytes of input, whatever they hold
+pub fn unpack_padding(buf: &[u8], padding: usize) -> Result<usize, UnpackError> {
+    ensure_len(buf, padding)?;
+    Ok(padding)
+}
 
-pub trait ByteUnpack: ByteSize + Sized + 'static {
-    fn unpack(buf: &[u8]) -> Result<Self, ()>;
+/// Skip the padding after `size` bytes of content up to `pad_to` bytes, failing if the content is longer
+pub fn unpack_pad_to(buf: &[u8], size: usize, pad_to: usize) -> Result<usize, UnpackError> {
+    if size > pad_to {
+        return Err(UnpackError::PaddingOverflow { offset: 0, size, pad_to });
+    }
+    unpack_padding(buf, pad_to - size)
+}
+
+/// Skip the constant `magic` bytes, failing if the input holds anything else
+pub fn unpack_magic(buf: &[u8], magic: &[u8]) -> Result<usize, UnpackError> {
+    ensure_len(buf, magic.len())?;
+    match buf[..magic.len()] == *magic {
+        true => Ok(magic.len()),
+        false => Err(UnpackError::InvalidMagic { offset: 0 }),
+    }
+}
+
+/// Unpack a bitfield group of `bytes` bytes in `endian`
+pub fn unpack_bits(buf: &[u8], bytes: usize, endian: Endian) -> Result<(u128, usize), UnpackError> {
+    ensure_len(buf, bytes)?;
+    let mut raw = [0; 16];
+    let bits = match endian {
+        Endian::Big => {
+            raw[16 - bytes..].copy_from_slice(&buf[..bytes]);
+            u128::from_be_bytes(raw)
+        }
+        Endian::Little => {
+            raw[..bytes].copy_from_slice(&buf[..bytes]);
+            u128::from_le_bytes(raw)
+        }
+    };
+    Ok((bits, bytes))
+}
+
+/// The value of the low `bits` bits of `raw`, failing if they name no value
+pub fn unpack_bitfield<T: BitField>(raw: u128, bits: u32) -> Result<T, UnpackError> {
+    let raw = raw & (u128::MAX >> (128 - bits));
+    T::from_bits(raw).ok_or(UnpackError::InvalidDiscriminant {
+        offset: 0,
+        value: raw as i128,
+    })
+}
+
+/// The `len` bytes of input after `offset`, failing instead of panicking past the end
+pub fn unpack_bytes(buf: &[u8], offset: usize, len: usize) -> Result<&[u8], UnpackError> {
+    let rest = unpack_rest(buf, offset)?;
+    ensure_len(rest, len).map_err(|e| e.shifted(offset))?;
+    Ok(&rest[..len])
+}
+
+fn read_array<const N: usize>(buf: &[u8]) -> Result<[u8; N], UnpackError> {
+    ensure_len(buf, N)?;
+    Ok(array::from_fn(|i| buf[i]))
+}
+
+/// Unpack a length prefix of type `L` into a `usize`
+fn unpack_len<L: LengthPrefix + ByteUnpackEndian>(
+    buf: &[u8],
+    endian: Endian,
+) -> Result<(usize, usize), UnpackError> {
+    let (len, offset) = L::unpack_endian_from(buf, endian)?;
+    let len = len.to_len().ok_or(UnpackError::LengthOverflow { offset: 0 })?;
+    Ok((len, offset))
+}
+
+/// Unpack `count` values back to back
+pub fn unpack_count<T: ByteUnpack>(buf: &[u8], count: usize) -> Result<(Vec<T>, usize), UnpackError> {
+    // count is untrusted input, never reserve more than the buffer could hold
+    let mut vec = Vec::with_capacity(count.min(buf.len()));
+
+    let mut offset = 0;
+    for _ in 0..count {
+        let (val_i, size) =
+            T::unpack_from(unpack_rest(buf, offset)?).map_err(|e| e.shifted(offset))?;
+        offset += size;
+        vec.push(val_i);
+    }
+
+    Ok((vec, offset))
+}
+
+/// Unpack `count` values back to back, each in `endian`
+pub fn unpack_count_endian<T: ByteUnpackEndian>(
+    buf: &[u8],
+    count: usize,
+    endian: Endian,
+) -> Result<(Vec<T>, usize), UnpackError> {
+    // count is untrusted input, never reserve more than the buffer could hold
+    let mut vec = Vec::with_capacity(count.min(buf.len()));
+
+    let mut offset = 0;
+    for _ in 0..count {
+        let (val_i, size) = T::unpack_endian_from(unpack_rest(buf, offset)?, endian)
+            .map_err(|e| e.shifted(offset))?;
+        offset += size;
+        vec.push(val_i);
+    }
+
+    Ok((vec, offset))
+}
+
+/// Unpack values behind a length prefix of type `L`
+pub fn unpack_sized<T: ByteUnpack, L: LengthPrefix + ByteUnpackEndian>(
+    buf: &[u8],
+) -> Result<(Vec<T>, usize), UnpackError> {
+    let (len, offset) = unpack_len::<L>(buf, Endian::Big)?;
+    let (vec, size) = unpack_count(&buf[offset..], len).map_err(|e| e.shifted(offset))?;
+    Ok((vec, offset + size))
+}
+
+/// Unpack values behind a length prefix of type `L`, the prefix and each value in `endian`
+pub fn unpack_sized_endian<T: ByteUnpackEndian, L: LengthPrefix + ByteUnpackEndian>(
+    buf: &[u8],
+    endian: Endian,
+) -> Result<(Vec<T>, usize), UnpackError> {
+    let (len, offset) = unpack_len::<L>(buf, endian)?;
+    let (vec, size) =
+        unpack_count_endian(&buf[offset..], len, endian).map_err(|e| e.shifted(offset))?;
+    Ok((vec, offset + size))
+}
+
+/// Unpack UTF-8 bytes behind a length prefix of type `L`
+fn unpack_string<L: LengthPrefix + ByteUnpackEndian>(
+    buf: &[u8],
+    endian: Endian,
+) -> Result<(String, usize), UnpackError> {
+    let (len, offset) = unpack_len::<L>(buf, endian)?;
+    ensure_len(&buf[offset..], len).map_err(|e| e.shifted(offset))?;
+
+    let string = String::from_utf8(buf[offset..offset + len].to_owned()).map_err(|e| {
+        UnpackError::InvalidUtf8 {
+            offset: offset + e.utf8_error().valid_up_to(),
+        }
+    })?;
+    Ok((string, offset + len))
+}
+
+/// Unpack values until `buf` is used up, with no length prefix to go by
+pub fn unpack_drain<T: ByteUnpack>(buf: &[u8]) -> Result<(Vec<T>, usize), UnpackError> {
+    let mut vec = Vec::new();
+
+    let mut offset = 0;
+    while offset < buf.len() {
+        let (val_i, size) = T::unpack_from(&buf[offset..]).map_err(|e| e.shifted(offset))?;
+        if size == 0 {
+            return Err(UnpackError::ZeroSizedElement { offset });
+        }
+        offset += size;
+        vec.push(val_i);
+    }
+
+    Ok((vec, offset))
+}
+
+/// Unpack values until `buf` is used up, each in `endian`
+pub fn unpack_drain_endian<T: ByteUnpackEndian>(
+    buf: &[u8],
+    endian: Endian,
+) -> Result<(Vec<T>, usize), UnpackError> {
+    let mut vec = Vec::new();
+
+    let mut offset = 0;
+    while offset < buf.len() {
+        let (val_i, size) =
+            T::unpack_endian_from(&buf[offset..], endian).map_err(|e| e.shifted(offset))?;
+        if size == 0 {
+            return Err(UnpackError::ZeroSizedElement { offset });
+        }
+        offset += size;
+        vec.push(val_i);
+    }
+
+    Ok((vec, offset))
+}
+
+pub trait ByteUnpack: ByteSize + Sized {
+    /// Unpack a value from the start of `buf`,
+    /// returning it together with the number of bytes consumed
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError>;
+
+    fn unpack(buf: &[u8]) -> Result<Self, UnpackError> {
+        Self::unpack_from(buf).map(|(val, _)| val)
+    }
+}
+
+/// Unpack an enum whose tag was already read from elsewhere
+pub trait UntaggedByteUnpack: Tagged + Sized {
+    fn unpack_untagged(tag: &Self::Tag, buf: &[u8]) -> Result<(Self, usize), UnpackError>;
+}
+
+/// Unpack an enum whose tag was already read from elsewhere in a byte order chosen by the caller,
+/// for a tag stored elsewhere in a `ByteUnpackEndian` container
+pub trait UntaggedByteUnpackEndian: Tagged + Sized {
+    fn unpack_untagged_endian(
+        tag: &Self::Tag,
+        buf: &[u8],
+        endian: Endian,
+    ) -> Result<(Self, usize), UnpackError>;
+}
+
+/// Unpack with a byte order chosen by the caller instead of by the type.
+///
+/// Containers pass the byte order on to their elements and length prefixes,
+/// types with a fixed byte order such as `LEu16` ignore it.
+pub trait ByteUnpackEndian: ByteUnpack {
+    fn unpack_endian_from(buf: &[u8], endian: Endian) -> Result<(Self, usize), UnpackError>;
 }
 
 impl ByteUnpack for u8 {
-    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-        Ok(buf[0])
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        Ok((read_array::<1>(buf)?[0], 1))
     }
 }
 
 // Default is Network (Big Endian) byte order
 impl ByteUnpack for u16 {
-    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-        Ok(Self::from_be_bytes(array::from_fn(|i| buf[i])))
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let bytes = read_array(buf)?;
+        Ok((Self::from_be_bytes(bytes), bytes.len()))
     }
 }
 
 // Default is Network (Big Endian) byte order
 impl ByteUnpack for u32 {
-    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-        Ok(Self::from_be_bytes(array::from_fn(|i| buf[i])))
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let bytes = read_array(buf)?;
+        Ok((Self::from_be_bytes(bytes), bytes.len()))
     }
 }
 
 // Default is Network (Big Endian) byte order
 impl ByteUnpack for u64 {
-    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-        Ok(Self::from_be_bytes(array::from_fn(|i| buf[i])))
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let bytes = read_array(buf)?;
+        Ok((Self::from_be_bytes(bytes), bytes.len()))
     }
 }
 
 // Default is Network (Big Endian) byte order
 impl ByteUnpack for u128 {
-    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-        Ok(Self::from_be_bytes(array::from_fn(|i| buf[i])))
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let bytes = read_array(buf)?;
+        Ok((Self::from_be_bytes(bytes), bytes.len()))
     }
 }
 
 impl ByteUnpack for i8 {
-    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-        Ok(buf[0] as i8)
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        Ok((read_array::<1>(buf)?[0] as i8, 1))
     }
 }
 
 // Default is Network (Big Endian) byte order
 impl ByteUnpack for i16 {
-    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-        Ok(Self::from_be_bytes(array::from_fn(|i| buf[i])))
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let bytes = read_array(buf)?;
+        Ok((Self::from_be_bytes(bytes), bytes.len()))
     }
 }
 
 // Default is Network (Big Endian) byte order
 impl ByteUnpack for i32 {
-    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-        Ok(Self::from_be_bytes(array::from_fn(|i| buf[i])))
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let bytes = read_array(buf)?;
+        Ok((Self::from_be_bytes(bytes), bytes.len()))
     }
 }
 
 // Default is Network (Big Endian) byte order
 impl ByteUnpack for i64 {
-    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-        Ok(Self::from_be_bytes(array::from_fn(|i| buf[i])))
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let bytes = read_array(buf)?;
+        Ok((Self::from_be_bytes(bytes), bytes.len()))
     }
 }
 
 // Default is Network (Big Endian) byte order
 impl ByteUnpack for i128 {
-    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-        Ok(Self::from_be_bytes(array::from_fn(|i| buf[i])))
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let bytes = read_array(buf)?;
+        Ok((Self::from_be_bytes(bytes), bytes.len()))
+    }
+}
+
+// Default is Network (Big Endian) byte order
+impl ByteUnpack for f32 {
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let bytes = read_array(buf)?;
+        Ok((Self::from_be_bytes(bytes), bytes.len()))
+    }
+}
+
+// Default is Network (Big Endian) byte order
+impl ByteUnpack for f64 {
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let bytes = read_array(buf)?;
+        Ok((Self::from_be_bytes(bytes), bytes.len()))
     }
 }
 
-macro_rules! imp_unpack_for_le_num {
-    ($le_u_type: ty, $u_type: ty) => {
-        impl ByteUnpack for $le_u_type {
-            fn unpack(buf: &[u8]) -> Result<Self, ()> {
-                Ok(Self(<$u_type>::from_le_bytes(array::from_fn(|i| buf[i]))))
+macro_rules! imp_unpack_for_wrapped_num {
+    ($wrapped_type: ty, $u_type: ty, $from_bytes: ident) => {
+        impl ByteUnpack for $wrapped_type {
+            fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+                let bytes = read_array(buf)?;
+                Ok((Self(<$u_type>::$from_bytes(bytes)), bytes.len()))
             }
         }
     };
 }
 
-imp_unpack_for_le_num!(crate::base::LEu16, u16);
-imp_unpack_for_le_num!(crate::base::LEu32, u32);
-imp_unpack_for_le_num!(crate::base::LEu64, u64);
-imp_unpack_for_le_num!(crate::base::LEu128, u128);
-imp_unpack_for_le_num!(crate::base::LEi16, i16);
-imp_unpack_for_le_num!(crate::base::LEi32, i32);
-imp_unpack_for_le_num!(crate::base::LEi64, i64);
-imp_unpack_for_le_num!(crate::base::LEi128, i128);
-
-impl<T: ByteUnpack + ConstByteSize, const N: usize> ByteUnpack for [T; N] {
-    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-        let stride = <T as ConstByteSize>::const_byte_size();
-        let arr = array::from_fn(|i| <T as ByteUnpack>::unpack(&buf[stride * i..]));
-        for e in &arr {
-            if e.is_err() {
-                Err(())?
+imp_unpack_for_wrapped_num!(crate::base::LEu16, u16, from_le_bytes);
+imp_unpack_for_wrapped_num!(crate::base::LEu32, u32, from_le_bytes);
+imp_unpack_for_wrapped_num!(crate::base::LEu64, u64, from_le_bytes);
+imp_unpack_for_wrapped_num!(crate::base::LEu128, u128, from_le_bytes);
+imp_unpack_for_wrapped_num!(crate::base::LEi16, i16, from_le_bytes);
+imp_unpack_for_wrapped_num!(crate::base::LEi32, i32, from_le_bytes);
+imp_unpack_for_wrapped_num!(crate::base::LEi64, i64, from_le_bytes);
+imp_unpack_for_wrapped_num!(crate::base::LEi128, i128, from_le_bytes);
+imp_unpack_for_wrapped_num!(crate::base::LEf32, f32, from_le_bytes);
+imp_unpack_for_wrapped_num!(crate::base::LEf64, f64, from_le_bytes);
+imp_unpack_for_wrapped_num!(crate::base::BEu16, u16, from_be_bytes);
+imp_unpack_for_wrapped_num!(crate::base::BEu32, u32, from_be_bytes);
+imp_unpack_for_wrapped_num!(crate::base::BEu64, u64, from_be_bytes);
+imp_unpack_for_wrapped_num!(crate::base::BEu128, u128, from_be_bytes);
+imp_unpack_for_wrapped_num!(crate::base::BEi16, i16, from_be_bytes);
+imp_unpack_for_wrapped_num!(crate::base::BEi32, i32, from_be_bytes);
+imp_unpack_for_wrapped_num!(crate::base::BEi64, i64, from_be_bytes);
+imp_unpack_for_wrapped_num!(crate::base::BEi128, i128, from_be_bytes);
+imp_unpack_for_wrapped_num!(crate::base::BEf32, f32, from_be_bytes);
+imp_unpack_for_wrapped_num!(crate::base::BEf64, f64, from_be_bytes);
+imp_unpack_for_wrapped_num!(crate::base::NEu16, u16, from_ne_bytes);
+imp_unpack_for_wrapped_num!(crate::base::NEu32, u32, from_ne_bytes);
+imp_unpack_for_wrapped_num!(crate::base::NEu64, u64, from_ne_bytes);
+imp_unpack_for_wrapped_num!(crate::base::NEu128, u128, from_ne_bytes);
+imp_unpack_for_wrapped_num!(crate::base::NEi16, i16, from_ne_bytes);
+imp_unpack_for_wrapped_num!(crate::base::NEi32, i32, from_ne_bytes);
+imp_unpack_for_wrapped_num!(crate::base::NEi64, i64, from_ne_bytes);
+imp_unpack_for_wrapped_num!(crate::base::NEi128, i128, from_ne_bytes);
+imp_unpack_for_wrapped_num!(crate::base::NEf32, f32, from_ne_bytes);
+imp_unpack_for_wrapped_num!(crate::base::NEf64, f64, from_ne_bytes);
+
+macro_rules! imp_unpack_endian_for_num {
+    ($num_type: ty) => {
+        impl ByteUnpackEndian for $num_type {
+            fn unpack_endian_from(
+                buf: &[u8],
+                endian: Endian,
+            ) -> Result<(Self, usize), UnpackError> {
+                let bytes = read_array(buf)?;
+                let val = match endian {
+                    Endian::Big => Self::from_be_bytes(bytes),
+                    Endian::Little => Self::from_le_bytes(bytes),
+                };
+                Ok((val, bytes.len()))
             }
         }
-        Ok(arr.map(|e| e.unwrap()))
+    };
+}
+
+imp_unpack_endian_for_num!(u8);
+imp_unpack_endian_for_num!(u16);
+imp_unpack_endian_for_num!(u32);
+imp_unpack_endian_for_num!(u64);
+imp_unpack_endian_for_num!(u128);
+imp_unpack_endian_for_num!(i8);
+imp_unpack_endian_for_num!(i16);
+imp_unpack_endian_for_num!(i32);
+imp_unpack_endian_for_num!(i64);
+imp_unpack_endian_for_num!(i128);
+imp_unpack_endian_for_num!(f32);
+imp_unpack_endian_for_num!(f64);
+
+macro_rules! imp_unpack_endian_for_wrapped_num {
+    ($wrapped_type: ty) => {
+        impl ByteUnpackEndian for $wrapped_type {
+            fn unpack_endian_from(
+                buf: &[u8],
+                _endian: Endian,
+            ) -> Result<(Self, usize), UnpackError> {
+                Self::unpack_from(buf)
+            }
+        }
+    };
+}
+
+imp_unpack_endian_for_wrapped_num!(crate::base::LEu16);
+imp_unpack_endian_for_wrapped_num!(crate::base::LEu32);
+imp_unpack_endian_for_wrapped_num!(crate::base::LEu64);
+imp_unpack_endian_for_wrapped_num!(crate::base::LEu128);
+imp_unpack_endian_for_wrapped_num!(crate::base::LEi16);
+imp_unpack_endian_for_wrapped_num!(crate::base::LEi32);
+imp_unpack_endian_for_wrapped_num!(crate::base::LEi64);
+imp_unpack_endian_for_wrapped_num!(crate::base::LEi128);
+imp_unpack_endian_for_wrapped_num!(crate::base::LEf32);
+imp_unpack_endian_for_wrapped_num!(crate::base::LEf64);
+imp_unpack_endian_for_wrapped_num!(crate::base::BEu16);
+imp_unpack_endian_for_wrapped_num!(crate::base::BEu32);
+imp_unpack_endian_for_wrapped_num!(crate::base::BEu64);
+imp_unpack_endian_for_wrapped_num!(crate::base::BEu128);
+imp_unpack_endian_for_wrapped_num!(crate::base::BEi16);
+imp_unpack_endian_for_wrapped_num!(crate::base::BEi32);
+imp_unpack_endian_for_wrapped_num!(crate::base::BEi64);
+imp_unpack_endian_for_wrapped_num!(crate::base::BEi128);
+imp_unpack_endian_for_wrapped_num!(crate::base::BEf32);
+imp_unpack_endian_for_wrapped_num!(crate::base::BEf64);
+imp_unpack_endian_for_wrapped_num!(crate::base::NEu16);
+imp_unpack_endian_for_wrapped_num!(crate::base::NEu32);
+imp_unpack_endian_for_wrapped_num!(crate::base::NEu64);
+imp_unpack_endian_for_wrapped_num!(crate::base::NEu128);
+imp_unpack_endian_for_wrapped_num!(crate::base::NEi16);
+imp_unpack_endian_for_wrapped_num!(crate::base::NEi32);
+imp_unpack_endian_for_wrapped_num!(crate::base::NEi64);
+imp_unpack_endian_for_wrapped_num!(crate::base::NEi128);
+imp_unpack_endian_for_wrapped_num!(crate::base::NEf32);
+imp_unpack_endian_for_wrapped_num!(crate::base::NEf64);
+
+impl ByteUnpack for bool {
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        match read_array::<1>(buf)?[0] {
+            0 => Ok((false, 1)),
+            1 => Ok((true, 1)),
+            value => Err(UnpackError::InvalidBool { offset: 0, value }),
+        }
+    }
+}
+
+impl ByteUnpack for crate::base::LenientBool {
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        Ok((Self(read_array::<1>(buf)?[0] != 0), 1))
+    }
+}
+
+// Encoded as the u32 scalar value, Network (Big Endian) byte order by default
+impl ByteUnpack for char {
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        Self::unpack_endian_from(buf, Endian::Big)
+    }
+}
+
+impl ByteUnpackEndian for bool {
+    fn unpack_endian_from(buf: &[u8], _endian: Endian) -> Result<(Self, usize), UnpackError> {
+        Self::unpack_from(buf)
+    }
+}
+
+impl ByteUnpackEndian for crate::base::LenientBool {
+    fn unpack_endian_from(buf: &[u8], _endian: Endian) -> Result<(Self, usize), UnpackError> {
+        Self::unpack_from(buf)
+    }
+}
+
+impl ByteUnpackEndian for char {
+    fn unpack_endian_from(buf: &[u8], endian: Endian) -> Result<(Self, usize), UnpackError> {
+        let (value, size) = u32::unpack_endian_from(buf, endian)?;
+        let val = char::from_u32(value).ok_or(UnpackError::InvalidChar { offset: 0, value })?;
+        Ok((val, size))
+    }
+}
+
+impl<T: ByteUnpack, const N: usize> ByteUnpack for [T; N] {
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let mut vec = Vec::with_capacity(N);
+
+        let mut offset = 0;
+        for _ in 0..N {
+            let (val_i, size) =
+                T::unpack_from(unpack_rest(buf, offset)?).map_err(|e| e.shifted(offset))?;
+            offset += size;
+            vec.push(val_i);
+        }
+
+        let Ok(arr) = Self::try_from(vec) else {
+            unreachable!("exactly N elements were unpacked");
+        };
+        Ok((arr, offset))
+    }
+}
+
+impl<T: ByteUnpackEndian, const N: usize> ByteUnpackEndian for [T; N] {
+    fn unpack_endian_from(buf: &[u8], endian: Endian) -> Result<(Self, usize), UnpackError> {
+        let mut vec = Vec::with_capacity(N);
+
+        let mut offset = 0;
+        for _ in 0..N {
+            let (val_i, size) = T::unpack_endian_from(unpack_rest(buf, offset)?, endian)
+                .map_err(|e| e.shifted(offset))?;
+            offset += size;
+            vec.push(val_i);
+        }
+
+        let Ok(arr) = Self::try_from(vec) else {
+            unreachable!("exactly N elements were unpacked");
+        };
+        Ok((arr, offset))
     }
 }
 
+// Elements are unpacked one after another, the same as the fields of a derived struct
+macro_rules! imp_unpack_for_tuple {
+    ($($elem_type: ident $index: tt),+) => {
+        impl<$($elem_type: ByteUnpack),+> ByteUnpack for ($($elem_type,)+) {
+            fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+                let mut offset = 0;
+                let val = ($({
+                    let (val_i, size) = $elem_type::unpack_from(unpack_rest(buf, offset)?)
+                        .map_err(|e| e.shifted(offset))?;
+                    offset += size;
+                    val_i
+                },)+);
+                Ok((val, offset))
+            }
+        }
+        impl<$($elem_type: ByteUnpackEndian),+> ByteUnpackEndian for ($($elem_type,)+) {
+            fn unpack_endian_from(buf: &[u8], endian: Endian) -> Result<(Self, usize), UnpackError> {
+                let mut offset = 0;
+                let val = ($({
+                    let (val_i, size) = $elem_type::unpack_endian_from(unpack_rest(buf, offset)?, endian)
+                        .map_err(|e| e.shifted(offset))?;
+                    offset += size;
+                    val_i
+                },)+);
+                Ok((val, offset))
+            }
+        }
+    };
+}
+
+imp_unpack_for_tuple!(A 0);
+imp_unpack_for_tuple!(A 0, B 1);
+imp_unpack_for_tuple!(A 0, B 1, C 2);
+imp_unpack_for_tuple!(A 0, B 1, C 2, D 3);
+imp_unpack_for_tuple!(A 0, B 1, C 2, D 3, E 4);
+imp_unpack_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+imp_unpack_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+imp_unpack_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+imp_unpack_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8);
+imp_unpack_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9);
+imp_unpack_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10);
+imp_unpack_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10, L 11);
+
 impl<T: ByteUnpack> ByteUnpack for Box<T> {
-    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-        Ok(Box::new(<T as ByteUnpack>::unpack(buf)?))
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let (val, size) = <T as ByteUnpack>::unpack_from(buf)?;
+        Ok((Box::new(val), size))
     }
 }
 
 impl<T: ByteUnpack> ByteUnpack for Rc<T> {
-    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-        Ok(Rc::new(<T as ByteUnpack>::unpack(buf)?))
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let (val, size) = <T as ByteUnpack>::unpack_from(buf)?;
+        Ok((Rc::new(val), size))
     }
 }
 
 impl<T: ByteUnpack> ByteUnpack for RefCell<T> {
-    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-        Ok(RefCell::new(<T as ByteUnpack>::unpack(buf)?))
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let (val, size) = <T as ByteUnpack>::unpack_from(buf)?;
+        Ok((RefCell::new(val), size))
     }
 }
 
 impl<T: ByteUnpack> ByteUnpack for Arc<T> {
-    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-        Ok(Arc::new(<T as ByteUnpack>::unpack(buf)?))
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let (val, size) = <T as ByteUnpack>::unpack_from(buf)?;
+        Ok((Arc::new(val), size))
     }
 }
 
-// NOTE: using DrainVec other than the last field is UB
-impl<T: ByteUnpack> ByteUnpack for DrainVec<T> {
-    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-        let mut vec = Vec::new();
-
-        let mut buf = buf;
-        while !buf.is_empty() {
-            let val_i = T::unpack(buf)?;
-            let byte_size = val_i.byte_size();
-            buf = &buf[byte_size..];
-            vec.push(val_i);
+impl<T: ByteUnpack> ByteUnpack for Option<T> {
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let (present, offset) = u8::unpack_from(buf)?;
+        match present {
+            0 => Ok((None, offset)),
+            1 => {
+                let (val, size) =
+                    T::unpack_from(unpack_rest(buf, offset)?).map_err(|e| e.shifted(offset))?;
+                Ok((Some(val), offset + size))
+            }
+            value => Err(UnpackError::InvalidDiscriminant {
+                offset: 0,
+                value: value.into(),
+            }),
         }
+    }
+}
 
-        Ok(Self(vec))
+// Takes the rest of the input, the derives reject a drain field anywhere but last
+impl<T: ByteUnpack> ByteUnpack for DrainVec<T> {
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let (vec, size) = unpack_drain(buf)?;
+        Ok((Self(vec), size))
     }
 }
 
 impl<T: ByteUnpack> ByteUnpack for SplatDrain<T> {
-    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-        Ok(Self::Drain(DrainVec::unpack(buf)?.0))
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let (vec, size) = unpack_drain(buf)?;
+        Ok((Self::Drain(vec), size))
     }
 }
 
-impl<T: ByteUnpack> ByteUnpack for SizedVec<T> {
-    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-        let mut buf = buf;
+impl<T: ByteUnpack, L: LengthPrefix + ByteUnpackEndian> ByteUnpack for SizedVec<T, L> {
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let (vec, size) = unpack_sized::<T, L>(buf)?;
+        Ok((Self::new(vec), size))
+    }
+}
 
-        let len = SizeType::unpack(buf)?;
-        buf = &buf[len.byte_size()..];
+impl<T: ByteUnpack> ByteUnpack for Vec<T> {
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        unpack_sized::<T, SizeType>(buf)
+    }
+}
 
-        let mut vec = Vec::with_capacity(len as usize);
+impl<T: ByteUnpack> ByteUnpack for Box<[T]> {
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let (vec, size) = Vec::unpack_from(buf)?;
+        Ok((vec.into_boxed_slice(), size))
+    }
+}
 
-        for _ in 0..len {
-            let val_i = T::unpack(buf)?;
-            let byte_size = val_i.byte_size();
-            buf = &buf[byte_size..];
-            vec.push(val_i);
-        }
+impl<T: ByteUnpack> ByteUnpack for VecDeque<T> {
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let (vec, size) = Vec::unpack_from(buf)?;
+        Ok((vec.into(), size))
+    }
+}
 
-        Ok(Self(vec))
+impl<T: ByteUnpack + ConstByteSize, const N: usize, const FILL: u8> ByteUnpack for Throw<T, N, FILL> {
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let size = Self::const_byte_size();
+        ensure_len(buf, size)?;
+        Ok((Self::new(), size))
     }
 }
 
-impl<T: ByteUnpack + ConstByteSize, const N: usize> ByteUnpack for Throw<T, N> {
-    fn unpack(_buf: &[u8]) -> Result<Self, ()> {
-        Ok(Self::new())
+impl<T: ByteUnpack + ConstByteSize, const N: usize, const FILL: u8> ByteUnpack
+    for Reserved<T, N, FILL>
+{
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let size = Self::const_byte_size();
+        ensure_len(buf, size)?;
+        if let Some(offset) = buf[..size].iter().position(|&byte| byte != FILL) {
+            return Err(UnpackError::InvalidReserved {
+                offset,
+                value: buf[offset],
+            });
+        }
+        Ok((Self::new(), size))
     }
 }
 
 impl ByteUnpack for String {
-    fn unpack(buf: &[u8]) -> Result<Self, ()> {
-        let mut buf = buf;
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        unpack_string::<SizeType>(buf, Endian::Big)
+    }
+}
 
-        let len = SizeType::unpack(buf)?;
-        buf = &buf[len.byte_size()..];
+impl<L: LengthPrefix + ByteUnpackEndian> ByteUnpack for SizedString<L> {
+    fn unpack_from(buf: &[u8]) -> Result<(Self, usize), UnpackError> {
+        let (string, size) = unpack_string::<L>(buf, Endian::Big)?;
+        Ok((Self::new(string), size))
+    }
+}
+
+impl<T: ByteUnpackEndian> ByteUnpackEndian for Box<T> {
+    fn unpack_endian_from(buf: &[u8], endian: Endian) -> Result<(Self, usize), UnpackError> {
+        let (val, size) = T::unpack_endian_from(buf, endian)?;
+        Ok((Box::new(val), size))
+    }
+}
+
+impl<T: ByteUnpackEndian> ByteUnpackEndian for Rc<T> {
+    fn unpack_endian_from(buf: &[u8], endian: Endian) -> Result<(Self, usize), UnpackError> {
+        let (val, size) = T::unpack_endian_from(buf, endian)?;
+        Ok((Rc::new(val), size))
+    }
+}
+
+impl<T: ByteUnpackEndian> ByteUnpackEndian for RefCell<T> {
+    fn unpack_endian_from(buf: &[u8], endian: Endian) -> Result<(Self, usize), UnpackError> {
+        let (val, size) = T::unpack_endian_from(buf, endian)?;
+        Ok((RefCell::new(val), size))
+    }
+}
+
+impl<T: ByteUnpackEndian> ByteUnpackEndian for Arc<T> {
+    fn unpack_endian_from(buf: &[u8], endian: Endian) -> Result<(Self, usize), UnpackError> {
+        let (val, size) = T::unpack_endian_from(buf, endian)?;
+        Ok((Arc::new(val), size))
+    }
+}
+
+impl<T: ByteUnpackEndian> ByteUnpackEndian for Option<T> {
+    fn unpack_endian_from(buf: &[u8], endian: Endian) -> Result<(Self, usize), UnpackError> {
+        let (present, offset) = u8::unpack_from(buf)?;
+        match present {
+            0 => Ok((None, offset)),
+            1 => {
+                let (val, size) = T::unpack_endian_from(unpack_rest(buf, offset)?, endian)
+                    .map_err(|e| e.shifted(offset))?;
+                Ok((Some(val), offset + size))
+            }
+            value => Err(UnpackError::InvalidDiscriminant {
+                offset: 0,
+                value: value.into(),
+            }),
+        }
+    }
+}
+
+impl<T: ByteUnpackEndian> ByteUnpackEndian for DrainVec<T> {
+    fn unpack_endian_from(buf: &[u8], endian: Endian) -> Result<(Self, usize), UnpackError> {
+        let (vec, size) = unpack_drain_endian(buf, endian)?;
+        Ok((Self(vec), size))
+    }
+}
+
+impl<T: ByteUnpackEndian> ByteUnpackEndian for SplatDrain<T> {
+    fn unpack_endian_from(buf: &[u8], endian: Endian) -> Result<(Self, usize), UnpackError> {
+        let (vec, size) = unpack_drain_endian(buf, endian)?;
+        Ok((Self::Drain(vec), size))
+    }
+}
+
+impl<T: ByteUnpackEndian, L: LengthPrefix + ByteUnpackEndian> ByteUnpackEndian for SizedVec<T, L> {
+    fn unpack_endian_from(buf: &[u8], endian: Endian) -> Result<(Self, usize), UnpackError> {
+        let (vec, size) = unpack_sized_endian::<T, L>(buf, endian)?;
+        Ok((Self::new(vec), size))
+    }
+}
+
+impl<T: ByteUnpackEndian> ByteUnpackEndian for Vec<T> {
+    fn unpack_endian_from(buf: &[u8], endian: Endian) -> Result<(Self, usize), UnpackError> {
+        unpack_sized_endian::<T, SizeType>(buf, endian)
+    }
+}
+
+impl<T: ByteUnpackEndian> ByteUnpackEndian for Box<[T]> {
+    fn unpack_endian_from(buf: &[u8], endian: Endian) -> Result<(Self, usize), UnpackError> {
+        let (vec, size) = Vec::unpack_endian_from(buf, endian)?;
+        Ok((vec.into_boxed_slice(), size))
+    }
+}
+
+impl<T: ByteUnpackEndian> ByteUnpackEndian for VecDeque<T> {
+    fn unpack_endian_from(buf: &[u8], endian: Endian) -> Result<(Self, usize), UnpackError> {
+        let (vec, size) = Vec::unpack_endian_from(buf, endian)?;
+        Ok((vec.into(), size))
+    }
+}
+
+impl<T: ByteUnpack + ConstByteSize, const N: usize, const FILL: u8> ByteUnpackEndian for Throw<T, N, FILL> {
+    fn unpack_endian_from(buf: &[u8], _endian: Endian) -> Result<(Self, usize), UnpackError> {
+        Self::unpack_from(buf)
+    }
+}
+
+impl<T: ByteUnpack + ConstByteSize, const N: usize, const FILL: u8> ByteUnpackEndian for Reserved<T, N, FILL> {
+    fn unpack_endian_from(buf: &[u8], _endian: Endian) -> Result<(Self, usize), UnpackError> {
+        Self::unpack_from(buf)
+    }
+}
+
+impl ByteUnpackEndian for String {
+    fn unpack_endian_from(buf: &[u8], endian: Endian) -> Result<(Self, usize), UnpackError> {
+        unpack_string::<SizeType>(buf, endian)
+    }
+}
 
-        Ok(String::from_utf8((&buf[..len as usize]).to_owned()).map_err(|_| ())?)
+impl<L: LengthPrefix + ByteUnpackEndian> ByteUnpackEndian for SizedString<L> {
+    fn unpack_endian_from(buf: &[u8], endian: Endian) -> Result<(Self, usize), UnpackError> {
+        let (string, size) = unpack_string::<L>(buf, endian)?;
+        Ok((Self::new(string), size))
     }
 }
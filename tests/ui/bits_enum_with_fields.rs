@@ -0,0 +1,17 @@
+use bytepack_proc_macro::{BytePack, ByteSize};
+
+#[derive(ByteSize, BytePack)]
+enum Payload {
+    Empty,
+    Value(u8),
+}
+
+#[derive(ByteSize, BytePack)]
+struct Header {
+    #[bytepack(bits = 4)]
+    payload: Payload,
+    #[bytepack(bits = 4)]
+    spare: u8,
+}
+
+fn main() {}
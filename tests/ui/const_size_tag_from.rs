@@ -0,0 +1,17 @@
+use bytepack::base::LEu32;
+use bytepack_proc_macro::{ByteSize, ConstByteSize};
+
+#[derive(ByteSize)]
+enum Body {
+    Empty,
+    Value(LEu32),
+}
+
+#[derive(ConstByteSize)]
+struct Frame {
+    kind: u16,
+    #[bytepack(tag_from = "kind")]
+    body: Body,
+}
+
+fn main() {}
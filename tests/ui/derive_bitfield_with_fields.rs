@@ -0,0 +1,9 @@
+use bytepack_proc_macro::BitField;
+
+#[derive(BitField)]
+enum Payload {
+    Empty,
+    Value(u8),
+}
+
+fn main() {}
@@ -0,0 +1,19 @@
+use bytepack::base::DrainVec;
+use bytepack_proc_macro::ByteUnpack;
+
+#[derive(ByteUnpack)]
+struct Tail {
+    rest: DrainVec<u8>,
+    last: u8,
+}
+
+#[derive(ByteUnpack)]
+enum Message {
+    Data {
+        #[bytepack(drain)]
+        rest: Vec<u8>,
+        last: u8,
+    },
+}
+
+fn main() {}
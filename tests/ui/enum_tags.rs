@@ -0,0 +1,19 @@
+use bytepack_proc_macro::ByteSize;
+
+#[derive(ByteSize)]
+enum TooLarge {
+    Small,
+    #[bytepack(tag = 256)]
+    Large,
+}
+
+#[derive(ByteSize)]
+enum Duplicate {
+    #[bytepack(tag = 1)]
+    First,
+    Zero,
+    #[bytepack(tag = 1)]
+    Second,
+}
+
+fn main() {}
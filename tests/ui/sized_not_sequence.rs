@@ -0,0 +1,9 @@
+use bytepack_proc_macro::{ByteSize, BytePack};
+
+#[derive(ByteSize, BytePack)]
+struct Header {
+    #[bytepack(sized = "u16")]
+    len: u32,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use bytepack_proc_macro::{BytePack, ByteSize, ByteUnpack};
+
+#[derive(ByteSize, BytePack, ByteUnpack)]
+union Bits {
+    int: u32,
+    float: f32,
+}
+
+fn main() {}